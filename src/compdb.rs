@@ -0,0 +1,98 @@
+use crate::rmake::rmake::{RMake, RMakeTarget};
+use serde::Serialize;
+use std::path::Path;
+
+/// Compiler names recognized as C/C++ compilers, cross prefixes like arm-linux-gnueabi- are allowed
+const RMAKE_COMPILERS: [&str; 6] = ["cc", "c++", "gcc", "g++", "clang", "clang++"];
+
+/// Extensions of C/C++ source files
+const RMAKE_SOURCE_EXTENSIONS: [&str; 6] = ["c", "cc", "cpp", "cxx", "c++", "C"];
+
+/// This represents an entry of compile_commands.json
+#[derive(Serialize)]
+struct RMakeCompileCommand {
+    directory: String,
+    file: String,
+    arguments: Vec<String>,
+    output: String,
+}
+
+/// Check if a program is a C/C++ compiler
+///
+/// # Arguments:
+///
+/// * program - The first word of a command
+fn is_compiler(program: &str) -> bool {
+    let name = Path::new(program)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(program);
+    RMAKE_COMPILERS
+        .iter()
+        .any(|compiler| name == *compiler || name.ends_with(&format!("-{}", compiler)))
+}
+
+/// Check if a file is a C/C++ source file
+///
+/// # Arguments:
+///
+/// * file - The file name
+fn is_source(file: &str) -> bool {
+    Path::new(file)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| RMAKE_SOURCE_EXTENSIONS.contains(&ext))
+}
+
+/// Find the compile commands of a target
+///
+/// # Arguments:
+///
+/// * target - The RMakeTarget, with its dependencies relative to the directory
+/// * directory - The directory the commands run in
+fn compile_commands(target: &RMakeTarget, directory: &str) -> Vec<RMakeCompileCommand> {
+    /* The source is the first dependency, as $< */
+    let source = match target.deps.as_ref().and_then(|deps| deps.first()) {
+        Some(source) if is_source(source) => source,
+        _ => return vec![],
+    };
+
+    target
+        .cmds
+        .iter()
+        .map(|cmd| cmd.split_whitespace().map(String::from).collect::<Vec<_>>())
+        .filter(|args| !args.is_empty() && is_compiler(&args[0]))
+        .filter(|args| args.iter().any(|arg| arg == source))
+        .map(|arguments| RMakeCompileCommand {
+            directory: directory.to_string(),
+            file: source.clone(),
+            arguments,
+            output: target.outputs[0].clone(),
+        })
+        .collect()
+}
+
+/// Render the compilation database of the requested goals
+///
+/// # Arguments:
+///
+/// * rmake - The loaded RMake object
+/// * names - The goal names, the first target is used if empty
+///
+/// Returns the compile_commands.json content
+pub fn render(rmake: &RMake, names: &[String]) -> Result<String, Box<dyn std::error::Error>> {
+    let entries = rmake
+        .build_order(names)
+        .iter()
+        .flat_map(|target| {
+            /* The commands of a target with a cwd run in it */
+            let directory = match &target.cwd {
+                Some(cwd) => rmake.resolve(cwd),
+                None => rmake.root.clone(),
+            };
+            /* The source must be named as in the commands, so relative to the cwd */
+            compile_commands(&target.located(&rmake.root), &directory.to_string_lossy())
+        })
+        .collect::<Vec<_>>();
+    Ok(serde_json::to_string_pretty(&entries)?)
+}
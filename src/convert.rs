@@ -0,0 +1,727 @@
+use serde_yaml::{Mapping, Value};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use tracing::{debug, warn};
+
+/// Makefile directives that are not converted
+const MAKE_DIRECTIVES: [&str; 15] = [
+    "include", "-include", "sinclude", "ifeq", "ifneq", "ifdef", "ifndef", "else", "endif",
+    "define", "endef", "export", "unexport", "override", "vpath",
+];
+
+/// This represents a rule of the Makefile
+#[derive(Debug, Clone, Default)]
+struct MakeRule {
+    /// The files produced by the rule
+    outputs: Vec<String>,
+
+    /// The prerequisites
+    deps: Vec<String>,
+
+    /// The recipe lines
+    cmds: Vec<String>,
+}
+
+/// This represents the parsed subset of a Makefile
+#[derive(Debug, Default)]
+struct Makefile {
+    /// Variables in definition order
+    variables: Vec<(String, String)>,
+
+    /// Explicit rules by target name
+    rules: HashMap<String, MakeRule>,
+
+    /// Target names in definition order
+    order: Vec<String>,
+
+    /// Pattern rules, like %.o: %.c
+    patterns: Vec<MakeRule>,
+
+    /// Targets declared in .PHONY
+    phony: HashSet<String>,
+}
+
+/// Replace ${NAME} references by the $(NAME) syntax supported by RMake
+///
+/// # Arguments:
+///
+/// * value - The String to convert
+fn convert_references(value: &str) -> String {
+    let mut out = String::new();
+    let mut chars = value.chars().peekable();
+    let mut depth = 0;
+    while let Some(c) = chars.next() {
+        if c == '$' && chars.peek() == Some(&'{') {
+            chars.next();
+            out.push_str("$(");
+            depth += 1;
+        } else if c == '}' && depth > 0 {
+            out.push(')');
+            depth -= 1;
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Find what a recipe line needs that RMake does not provide, since it
+/// runs each command split on whitespace, without a shell
+///
+/// # Arguments:
+///
+/// * cmd - The recipe line, with ${NAME} already converted
+///
+/// Returns the descriptions of the unsupported constructs, each reported once
+fn recipe_problems(cmd: &str) -> Vec<String> {
+    let shell = |syntax: String| {
+        Some(format!(
+            "{} needs a shell, which RMake does not use",
+            syntax
+        ))
+    };
+    let chars = cmd.chars().collect::<Vec<_>>();
+    let mut problems: Vec<String> = vec![];
+    let mut index = 0;
+    while index < chars.len() {
+        let next = chars.get(index + 1).copied();
+        let (problem, length) = match (chars[index], next) {
+            ('$', Some('$')) => (shell("$$".to_string()), 2),
+            ('$', Some(auto @ ('*' | '?' | '+' | '|' | '%'))) => (
+                Some(format!("automatic variable ${} is not supported", auto)),
+                2,
+            ),
+            /* Directory and file parts, like $(@D) */
+            ('$', Some('(')) => match chars.get(index + 2..index + 5) {
+                Some(&[auto, part, ')'])
+                    if "@<^*?+|%".contains(auto) && (part == 'D' || part == 'F') =>
+                {
+                    (
+                        Some(format!(
+                            "automatic variable $({}{}) is not supported",
+                            auto, part
+                        )),
+                        5,
+                    )
+                }
+                _ => (None, 2),
+            },
+            /* $@, $< and $^ are supported, the character after $ is never an operator */
+            ('$', _) => (None, 2),
+            (c @ ('&' | '|'), Some(d)) if c == d => (shell(format!("{}{}", c, d)), 2),
+            (c @ ('|' | ';' | '>' | '<' | '`'), _) => (shell(c.to_string()), 1),
+            (c @ ('*' | '?' | '['), _) => (shell(format!("glob {}", c)), 1),
+            _ => (None, 1),
+        };
+        if let Some(problem) = problem {
+            if !problems.contains(&problem) {
+                problems.push(problem);
+            }
+        }
+        index += length;
+    }
+    problems
+}
+
+/// Report the recipe lines that will not run as in make
+///
+/// # Arguments:
+///
+/// * line - The 1-based line number in the Makefile
+/// * cmd - The recipe line, with ${NAME} already converted
+fn check_recipe(line: usize, cmd: &str) {
+    for problem in recipe_problems(cmd) {
+        warn!("line {}: {}: {}", line, problem, cmd);
+    }
+}
+
+/// Join the lines ending with a backslash with the next one
+///
+/// # Arguments:
+///
+/// * content - The Makefile content
+fn join_continuations(content: &str) -> Vec<String> {
+    let mut lines: Vec<String> = vec![];
+    let mut continued = false;
+    for line in content.lines() {
+        let (line, continues) = match line.strip_suffix('\\') {
+            Some(line) => (line, true),
+            None => (line, false),
+        };
+
+        if continued {
+            let last = lines.last_mut().unwrap();
+            last.truncate(last.trim_end().len());
+            last.push(' ');
+            last.push_str(line.trim_start());
+        } else {
+            lines.push(line.to_string());
+        }
+        continued = continues;
+    }
+    lines
+}
+
+/// Match a file name against a pattern like %.o
+///
+/// # Arguments:
+///
+/// * pattern - The pattern, with one %
+/// * name - The file name
+///
+/// Returns the stem matched by %
+fn match_pattern<'a>(pattern: &str, name: &'a str) -> Option<&'a str> {
+    let (prefix, suffix) = pattern.split_once('%')?;
+    if name.len() < prefix.len() + suffix.len() {
+        return None;
+    }
+    name.strip_prefix(prefix)?.strip_suffix(suffix)
+}
+
+impl Makefile {
+    /// Parse the supported subset of the Makefile syntax
+    ///
+    /// # Arguments:
+    ///
+    /// * content - The Makefile content
+    fn parse(content: &str) -> Makefile {
+        let mut makefile = Makefile::default();
+        /* The rules receiving the recipe lines that follow */
+        let mut current: Vec<String> = vec![];
+        let mut current_pattern: Option<usize> = None;
+
+        for (index, line) in join_continuations(content).iter().enumerate() {
+            /* Recipe lines start with a tab */
+            if let Some(cmd) = line.strip_prefix('\t') {
+                let cmd = cmd.trim_start_matches(['@', '+']);
+                if cmd.starts_with('-') {
+                    warn!(
+                        "line {}: ignoring errors with - is not supported: {}",
+                        index + 1,
+                        cmd
+                    );
+                }
+                let cmd = convert_references(cmd.trim_start_matches('-').trim());
+                if cmd.is_empty() {
+                    continue;
+                }
+                check_recipe(index + 1, &cmd);
+                if let Some(pattern) = current_pattern {
+                    makefile.patterns[pattern].cmds.push(cmd);
+                } else if current.is_empty() {
+                    warn!("line {}: recipe without a rule: {}", index + 1, cmd);
+                } else {
+                    for target in current.iter() {
+                        makefile
+                            .rules
+                            .get_mut(target)
+                            .unwrap()
+                            .cmds
+                            .push(cmd.clone());
+                    }
+                }
+                continue;
+            }
+
+            /* Drop comments */
+            let line = match line.find('#') {
+                Some(pos) => &line[..pos],
+                None => line.as_str(),
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            current.clear();
+            current_pattern = None;
+
+            let first_word = line.split_whitespace().next().unwrap_or_default();
+            if MAKE_DIRECTIVES.contains(&first_word) {
+                warn!(
+                    "line {}: directive {} is not supported, ignoring: {}",
+                    index + 1,
+                    first_word,
+                    line.trim()
+                );
+                continue;
+            }
+
+            let eq = line.find('=');
+            let colon = line.find(':');
+            let is_assignment = match (eq, colon) {
+                (Some(_), None) => true,
+                (Some(eq), Some(colon)) => {
+                    colon > eq
+                        || line[colon..].starts_with(":=")
+                        || line[colon..].starts_with("::=")
+                }
+                _ => false,
+            };
+
+            if is_assignment {
+                makefile.parse_assignment(line, eq.unwrap());
+            } else if colon.is_some() {
+                /* Like make, targets and prerequisites are expanded when the rule is read */
+                let (line, inline_cmd) = match line.split_once(';') {
+                    Some((line, cmd)) => (line, Some(convert_references(cmd.trim()))),
+                    None => (line, None),
+                };
+                if let Some(cmd) = &inline_cmd {
+                    check_recipe(index + 1, cmd);
+                }
+                let line = makefile.expand(&convert_references(line), &mut vec![]);
+                let colon = match line.find(':') {
+                    Some(colon) => colon,
+                    None => {
+                        warn!(
+                            "line {}: rule without a colon after expansion, ignoring: {}",
+                            index + 1,
+                            line.trim()
+                        );
+                        continue;
+                    }
+                };
+                let (targets, grouped) = match line[..colon].strip_suffix('&') {
+                    Some(targets) => (targets, true),
+                    None => (&line[..colon], false),
+                };
+                let deps = line[colon + 1..].trim_start_matches(':');
+
+                let targets = targets
+                    .split_whitespace()
+                    .map(String::from)
+                    .collect::<Vec<_>>();
+                let deps = deps
+                    .split_whitespace()
+                    .filter(|dep| *dep != "|")
+                    .map(String::from)
+                    .collect::<Vec<_>>();
+
+                if targets.iter().any(|t| t == ".PHONY") {
+                    makefile.phony.extend(deps);
+                    continue;
+                }
+                if let Some(special) = targets.iter().find(|t| t.starts_with('.')) {
+                    if special
+                        .chars()
+                        .skip(1)
+                        .all(|c| c.is_ascii_uppercase() || c == '_')
+                    {
+                        warn!("line {}: special target {} is ignored", index + 1, special);
+                        continue;
+                    }
+                }
+
+                if targets.iter().any(|t| t.contains('%')) {
+                    makefile.patterns.push(MakeRule {
+                        outputs: targets,
+                        deps,
+                        cmds: inline_cmd.into_iter().collect(),
+                    });
+                    current_pattern = Some(makefile.patterns.len() - 1);
+                    continue;
+                }
+
+                /* Grouped targets share one rule, others get their own copy */
+                let groups = if grouped {
+                    vec![targets]
+                } else {
+                    targets.into_iter().map(|t| vec![t]).collect()
+                };
+                for outputs in groups {
+                    let name = outputs[0].clone();
+                    let rule = makefile.rules.entry(name.clone()).or_insert_with(|| {
+                        makefile.order.push(name.clone());
+                        MakeRule::default()
+                    });
+                    if outputs.len() > 1 {
+                        rule.outputs = outputs;
+                    }
+                    for dep in deps.iter() {
+                        if !rule.deps.contains(dep) {
+                            rule.deps.push(dep.clone());
+                        }
+                    }
+                    if let Some(cmd) = &inline_cmd {
+                        rule.cmds.push(cmd.clone());
+                    }
+                    current.push(name);
+                }
+            } else {
+                warn!(
+                    "line {}: cannot parse, ignoring: {}",
+                    index + 1,
+                    line.trim()
+                );
+            }
+        }
+
+        makefile
+    }
+
+    /// Expand the $(NAME) references of a String with the variables defined so far
+    ///
+    /// Undefined variables come from the environment, or are empty, like in
+    /// make. Function calls like $(wildcard *.c) are kept as they are.
+    ///
+    /// # Arguments:
+    ///
+    /// * value - The String to expand, with ${NAME} already converted
+    /// * seen - The variables being expanded, to stop on recursive definitions
+    fn expand(&self, value: &str, seen: &mut Vec<String>) -> String {
+        let mut out = String::new();
+        let mut rest = value;
+        while let Some(start) = rest.find("$(") {
+            out.push_str(&rest[..start]);
+            let inner = &rest[start + 2..];
+
+            /* Find the matching parenthesis */
+            let mut depth = 1;
+            let end = inner.char_indices().find_map(|(i, c)| {
+                match c {
+                    '(' => depth += 1,
+                    ')' => depth -= 1,
+                    _ => {}
+                }
+                (depth == 0).then_some(i)
+            });
+            let end = match end {
+                Some(end) => end,
+                None => {
+                    out.push_str(&rest[start..]);
+                    return out;
+                }
+            };
+            let name = &inner[..end];
+            rest = &inner[end + 1..];
+
+            if name.contains(char::is_whitespace) || name.contains(':') {
+                warn!("Function call $({}) in a rule is not expanded", name);
+                out.push_str(&format!("$({})", name));
+            } else if seen.iter().any(|s| s == name) {
+                warn!("Variable {} references itself, it is not expanded", name);
+            } else {
+                match self.variables.iter().find(|(n, _)| n == name) {
+                    Some((_, value)) => {
+                        seen.push(name.to_string());
+                        out.push_str(&self.expand(value, seen));
+                        seen.pop();
+                    }
+                    None => match std::env::var(name) {
+                        Ok(value) => out.push_str(&value),
+                        Err(_) => warn!("Variable {} is not defined, it expands to nothing", name),
+                    },
+                }
+            }
+        }
+        out.push_str(rest);
+        out
+    }
+
+    /// Parse a variable assignment, like NAME = value or NAME := value
+    ///
+    /// # Arguments:
+    ///
+    /// * line - The Makefile line
+    /// * eq - The position of the = sign
+    fn parse_assignment(&mut self, line: &str, eq: usize) {
+        let lhs = &line[..eq];
+        let value = convert_references(line[eq + 1..].trim());
+        let name = lhs
+            .trim_end_matches([':', '?', '+', '!'])
+            .trim()
+            .to_string();
+        let op = &lhs[name.len()..].trim();
+
+        /* Simply expanded variables take the values defined so far */
+        let value = if *op == ":" || *op == "::" {
+            self.expand(&value, &mut vec![])
+        } else {
+            value
+        };
+        let existing = self.variables.iter_mut().find(|(n, _)| *n == name);
+
+        match (*op, existing) {
+            ("+", Some((_, old))) => {
+                old.push(' ');
+                old.push_str(&value);
+            }
+            ("?", Some(_)) => debug!("{} is already defined, ignoring ?=", name),
+            (op, Some((_, old))) => *old = shell_value(op, value),
+            (op, None) => self.variables.push((name, shell_value(op, value))),
+        }
+
+        /// Turn a != assignment into a $(shell) call
+        fn shell_value(op: &str, value: String) -> String {
+            if op == "!" {
+                format!("$(shell {})", value)
+            } else {
+                value
+            }
+        }
+    }
+
+    /// Create explicit rules from pattern rules for the files that need them
+    ///
+    /// # Arguments:
+    ///
+    /// * base - The directory of the Makefile, used to check if sources exist
+    fn instantiate_patterns(&mut self, base: &Path) {
+        /* Every file mentioned as target or prerequisite, in order */
+        let mut mentioned = self.order.clone();
+        for name in self.order.iter() {
+            for dep in self.rules[name].deps.iter() {
+                if !mentioned.contains(dep) {
+                    mentioned.push(dep.clone());
+                }
+            }
+        }
+
+        let mut used = vec![false; self.patterns.len()];
+        for name in mentioned.iter() {
+            if self
+                .rules
+                .get(name)
+                .is_some_and(|rule| !rule.cmds.is_empty())
+            {
+                continue;
+            }
+
+            for (index, pattern) in self.patterns.iter().enumerate() {
+                let stem = match pattern.outputs.iter().find_map(|p| match_pattern(p, name)) {
+                    Some(stem) => stem,
+                    None => continue,
+                };
+                let deps = pattern
+                    .deps
+                    .iter()
+                    .map(|dep| dep.replacen('%', stem, 1))
+                    .collect::<Vec<_>>();
+
+                /* Like make, a pattern only applies if its prerequisites exist or can be made */
+                let applies = deps
+                    .iter()
+                    .all(|dep| mentioned.contains(dep) || base.join(dep).exists());
+                if !applies {
+                    continue;
+                }
+
+                debug!("Instantiating pattern {:?} for {}", pattern.outputs, name);
+                used[index] = true;
+                let cmds = pattern.cmds.clone();
+                let rule = self.rules.entry(name.clone()).or_insert_with(|| {
+                    self.order.push(name.clone());
+                    MakeRule::default()
+                });
+                let mut all_deps = deps;
+                for dep in rule.deps.iter() {
+                    if !all_deps.contains(dep) {
+                        all_deps.push(dep.clone());
+                    }
+                }
+                rule.deps = all_deps;
+                rule.cmds = cmds;
+                break;
+            }
+        }
+
+        for (index, pattern) in self.patterns.iter().enumerate() {
+            if !used[index] {
+                warn!(
+                    "Pattern rule {} is not used by any target, it is not converted",
+                    pattern.outputs.join(" ")
+                );
+            }
+        }
+    }
+
+    /// Build the YAML structure loaded by RMake
+    fn to_yaml(&self) -> Mapping {
+        /// A single String is written as is, more as a Sequence
+        fn string_or_sequence(values: &[String]) -> Value {
+            if values.len() == 1 {
+                Value::String(values[0].clone())
+            } else {
+                Value::Sequence(values.iter().cloned().map(Value::String).collect())
+            }
+        }
+
+        let mut mapping = Mapping::new();
+        for (name, value) in self.variables.iter() {
+            if self.rules.contains_key(name) {
+                warn!(
+                    "Variable {} has the same name as a target, ignoring it",
+                    name
+                );
+                continue;
+            }
+            mapping.insert(Value::String(name.clone()), Value::String(value.clone()));
+        }
+
+        for name in self.order.iter() {
+            let rule = &self.rules[name];
+            let mut target = Mapping::new();
+            if self.phony.contains(name) {
+                target.insert("phony".into(), Value::Bool(true));
+            }
+            if !rule.outputs.is_empty() {
+                target.insert("outputs".into(), string_or_sequence(&rule.outputs));
+            }
+            if !rule.deps.is_empty() {
+                target.insert("dep".into(), string_or_sequence(&rule.deps));
+            }
+            target.insert("cmd".into(), string_or_sequence(&rule.cmds));
+            mapping.insert(Value::String(name.clone()), Value::Mapping(target));
+        }
+
+        for name in self.phony.iter() {
+            if !self.rules.contains_key(name) {
+                warn!("Phony target {} has no rule, ignoring it", name);
+            }
+        }
+        mapping
+    }
+}
+
+/// Convert a GNU Makefile to the RMakefile.yml format
+///
+/// Supported: simple and recursive variables, explicit and grouped rules,
+/// pattern rules (instantiated for the files that need them), .PHONY and
+/// tab-prefixed recipes. Other constructs are reported and ignored.
+///
+/// # Arguments:
+///
+/// * path - The Makefile path
+///
+/// Returns the RMakefile.yml content
+pub fn convert(path: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let content = std::fs::read_to_string(path)?;
+    let base = Path::new(path).parent().unwrap_or(Path::new("."));
+
+    let mut makefile = Makefile::parse(&content);
+    makefile.instantiate_patterns(base);
+    if makefile.order.is_empty() {
+        return Err(format!("No rule found in {}", path).into());
+    }
+
+    Ok(serde_yaml::to_string(&makefile.to_yaml())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recipe_problems_reports_shell_syntax() {
+        let shell = |syntax: &str| format!("{} needs a shell, which RMake does not use", syntax);
+        assert_eq!(
+            recipe_problems("$(CC) $^ -o $@ && echo done"),
+            vec![shell("&&")]
+        );
+        assert_eq!(recipe_problems("rm -f *.o"), vec![shell("glob *")]);
+        assert_eq!(
+            recipe_problems("cat $< | sort > $@; echo $$HOME"),
+            vec![shell("|"), shell(">"), shell(";"), shell("$$")]
+        );
+        assert!(recipe_problems("$(CC) $(CFLAGS) -c $< -o $@ $^").is_empty());
+    }
+
+    #[test]
+    fn recipe_problems_reports_make_automatic_variables() {
+        assert_eq!(
+            recipe_problems("cc -c $*.c -o $(@D)/x $? $(<F)"),
+            vec![
+                "automatic variable $* is not supported",
+                "automatic variable $(@D) is not supported",
+                "automatic variable $? is not supported",
+                "automatic variable $(<F) is not supported",
+            ]
+        );
+        assert!(recipe_problems("echo $(DIR) $(@)").is_empty());
+    }
+
+    #[test]
+    fn join_continuations_joins_backslash_lines() {
+        let lines = join_continuations("SRCS = a.c \\\n    b.c\nall: prog\n");
+        assert_eq!(lines, vec!["SRCS = a.c b.c", "all: prog"]);
+    }
+
+    #[test]
+    fn match_pattern_returns_the_stem() {
+        assert_eq!(match_pattern("%.o", "main.o"), Some("main"));
+        assert_eq!(match_pattern("lib%.a", "libfoo.a"), Some("foo"));
+        assert_eq!(match_pattern("%.o", "main.c"), None);
+        assert_eq!(match_pattern("a%a", "a"), None);
+        assert_eq!(match_pattern("main.o", "main.o"), None);
+    }
+
+    #[test]
+    fn parse_assignment_operators() {
+        let makefile =
+            Makefile::parse("A = 1\nA += 2\nB := x\nB ?= y\nC ?= z\nD != echo hi\nE ::= ${A}\n");
+        assert_eq!(
+            makefile.variables,
+            vec![
+                ("A".to_string(), "1 2".to_string()),
+                ("B".to_string(), "x".to_string()),
+                ("C".to_string(), "z".to_string()),
+                ("D".to_string(), "$(shell echo hi)".to_string()),
+                ("E".to_string(), "1 2".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_phony_targets() {
+        let makefile = Makefile::parse(".PHONY: all clean\nall: prog\nclean:\n\trm -f prog\n");
+        assert!(makefile.phony.contains("all"));
+        assert!(makefile.phony.contains("clean"));
+        assert_eq!(makefile.order, vec!["all", "clean"]);
+
+        let yaml = makefile.to_yaml();
+        assert_eq!(yaml["clean"]["phony"], Value::Bool(true));
+    }
+
+    #[test]
+    fn parse_grouped_and_separate_targets() {
+        let makefile = Makefile::parse("parser.c parser.h &: parser.y\n\tbison -d parser.y\n");
+        assert_eq!(makefile.order, vec!["parser.c"]);
+        assert_eq!(
+            makefile.rules["parser.c"].outputs,
+            vec!["parser.c", "parser.h"]
+        );
+        assert_eq!(makefile.rules["parser.c"].cmds, vec!["bison -d parser.y"]);
+
+        let makefile = Makefile::parse("a b: c\n\ttouch $@\n");
+        assert_eq!(makefile.order, vec!["a", "b"]);
+        assert!(makefile.rules["a"].outputs.is_empty());
+        assert_eq!(makefile.rules["b"].deps, vec!["c"]);
+    }
+
+    #[test]
+    fn parse_expands_targets_and_prerequisites() {
+        let makefile = Makefile::parse(
+            "OBJS = main.o ${EXTRA}\nEXTRA = util.o\nBIN := prog\n$(BIN): $(OBJS) ; cc $^ -o $(BIN)\n",
+        );
+        assert_eq!(makefile.order, vec!["prog"]);
+        assert_eq!(makefile.rules["prog"].deps, vec!["main.o", "util.o"]);
+        /* Recipes are kept unexpanded */
+        assert_eq!(makefile.rules["prog"].cmds, vec!["cc $^ -o $(BIN)"]);
+    }
+
+    #[test]
+    fn instantiate_patterns_for_needed_files() {
+        let base = std::env::temp_dir().join(format!("rmake-convert-{}", std::process::id()));
+        std::fs::create_dir_all(&base).unwrap();
+        std::fs::write(base.join("main.c"), "").unwrap();
+
+        let mut makefile = Makefile::parse(
+            "OBJS = main.o\nprog: $(OBJS)\n\tcc $^ -o $@\n%.o: %.c\n\tcc -c $< -o $@\n%.o: %.s\n\tas $< -o $@\n",
+        );
+        makefile.instantiate_patterns(&base);
+        std::fs::remove_dir_all(&base).unwrap();
+
+        assert_eq!(makefile.order, vec!["prog", "main.o"]);
+        assert_eq!(makefile.rules["main.o"].deps, vec!["main.c"]);
+        assert_eq!(makefile.rules["main.o"].cmds, vec!["cc -c $< -o $@"]);
+    }
+}
@@ -0,0 +1,103 @@
+use crate::rmake::rmake::{RMake, RMakeOrigin};
+
+/// The automatic variables, set by RMake for each target
+const RMAKE_AUTOMATIC_VARIABLES: [(&str, &str); 3] = [
+    ("@", "The target name"),
+    ("<", "The first dependency"),
+    ("^", "All the dependencies"),
+];
+
+/// Render the database of variables and rules, like `make -p`
+///
+/// Variables are printed with their raw value and their origin, rules are
+/// printed in file order with their expanded commands.
+///
+/// # Arguments:
+///
+/// * rmake - The loaded RMake object
+///
+/// Returns the database content
+pub fn render(rmake: &RMake) -> String {
+    let mut out = String::from("# Variables\n\n");
+
+    let variables = rmake.sorted_variables();
+    for (name, variable) in variables.iter() {
+        match &variable.location {
+            Some(location) => out.push_str(&format!("# {} ({})\n", variable.origin, location)),
+            None => out.push_str(&format!("# {}\n", variable.origin)),
+        }
+        out.push_str(&format!("{} = {}\n", name, variable.value));
+    }
+
+    /* Undefined variables are looked up in the environment */
+    let mut env_vars = std::env::vars()
+        .filter(|(name, _)| !variables.iter().any(|(v, _)| *v == name))
+        .collect::<Vec<_>>();
+    env_vars.sort();
+    for (name, value) in env_vars {
+        out.push_str(&format!(
+            "# {}\n{} = {}\n",
+            RMakeOrigin::Environment,
+            name,
+            value
+        ));
+    }
+
+    for (name, description) in RMAKE_AUTOMATIC_VARIABLES {
+        out.push_str(&format!(
+            "# {}: {}\n{}\n",
+            RMakeOrigin::Automatic,
+            description,
+            name
+        ));
+    }
+
+    if !rmake.exports.is_empty() {
+        out.push_str(&format!("# exported\nexport {}\n", rmake.exports.join(" ")));
+    }
+
+    out.push_str("\n# Rules\n");
+    let targets = rmake.sorted_targets();
+    for target in targets {
+        out.push('\n');
+        if let Some(location) = &target.location {
+            out.push_str(&format!("# defined at {}\n", location));
+        }
+        if target.phony {
+            out.push_str("# phony\n");
+        }
+        if let Some(cwd) = &target.cwd {
+            out.push_str(&format!("# cwd: {}\n", cwd));
+        }
+        if let Some(depfile) = &target.depfile {
+            out.push_str(&format!("# depfile: {}\n", depfile));
+        }
+        let mut vars = target.vars.iter().collect::<Vec<_>>();
+        vars.sort();
+        for (name, value) in vars {
+            out.push_str(&format!(
+                "# {}: {} = {}\n",
+                RMakeOrigin::Target,
+                name,
+                value
+            ));
+        }
+        let mut env = target.env.iter().collect::<Vec<_>>();
+        env.sort();
+        for (name, value) in env {
+            out.push_str(&format!("# env: {}={}\n", name, value));
+        }
+        if target.inherit_vars {
+            out.push_str("# prerequisites inherit the target-specific variables\n");
+        }
+        out.push_str(&format!(
+            "{}: {}\n",
+            target.outputs.join(" "),
+            target.deps.as_deref().unwrap_or_default().join(" ")
+        ));
+        for cmd in target.cmds.iter().filter(|cmd| !cmd.trim().is_empty()) {
+            out.push_str(&format!("\t{}\n", cmd));
+        }
+    }
+    out
+}
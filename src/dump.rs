@@ -0,0 +1,119 @@
+use crate::rmake::rmake::{RMake, RMakeLocation};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// The version of the JSON schema, bump it on incompatible changes
+pub const RMAKE_DUMP_VERSION: u32 = 1;
+
+/// This represents a source location in the JSON schema
+#[derive(Serialize)]
+struct DumpLocation<'a> {
+    file: &'a str,
+    line: usize,
+    column: usize,
+}
+
+impl<'a> From<&'a RMakeLocation> for DumpLocation<'a> {
+    fn from(location: &'a RMakeLocation) -> Self {
+        DumpLocation {
+            file: &location.file,
+            line: location.line,
+            column: location.column,
+        }
+    }
+}
+
+/// This represents a variable in the JSON schema
+#[derive(Serialize)]
+struct DumpVariable<'a> {
+    name: &'a str,
+    value: &'a str,
+    expanded: String,
+    location: Option<DumpLocation<'a>>,
+}
+
+/// This represents a target in the JSON schema
+#[derive(Serialize)]
+struct DumpTarget<'a> {
+    name: &'a str,
+    outputs: &'a [String],
+    deps: &'a [String],
+    cmds: &'a [String],
+    depfile: Option<&'a str>,
+    phony: bool,
+    desc: Option<&'a str>,
+    hidden: bool,
+    vars: BTreeMap<&'a str, &'a str>,
+    inherit_vars: bool,
+    env: BTreeMap<&'a str, &'a str>,
+    cwd: Option<&'a str>,
+    location: Option<DumpLocation<'a>>,
+}
+
+/// This represents a whole RMakefile in the JSON schema
+#[derive(Serialize)]
+struct Dump<'a> {
+    version: u32,
+    variables: Vec<DumpVariable<'a>>,
+    exports: &'a [String],
+    targets: Vec<DumpTarget<'a>>,
+}
+
+/// Serialize the loaded RMake object to JSON
+///
+/// Variables and targets are listed in file order, with their raw and
+/// expanded values.
+///
+/// # Arguments:
+///
+/// * rmake - The loaded RMake object
+///
+/// Returns the pretty printed JSON content
+pub fn render(rmake: &RMake) -> Result<String, serde_json::Error> {
+    /* Entries without a location, like command line variables, are sorted by name */
+    let variables = rmake.sorted_variables();
+    let variables = variables
+        .into_iter()
+        .map(|(_, variable)| DumpVariable {
+            name: &variable.name,
+            value: &variable.value,
+            expanded: variable.expand(&rmake.variables, &rmake.root),
+            location: variable.location.as_ref().map(DumpLocation::from),
+        })
+        .collect::<Vec<_>>();
+
+    let targets = rmake.sorted_targets();
+    let targets = targets
+        .into_iter()
+        .map(|target| DumpTarget {
+            name: &target.name,
+            outputs: &target.outputs,
+            deps: target.deps.as_deref().unwrap_or_default(),
+            cmds: &target.cmds,
+            depfile: target.depfile.as_deref(),
+            phony: target.phony,
+            desc: target.desc.as_deref(),
+            hidden: target.hidden,
+            vars: target
+                .vars
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_str()))
+                .collect(),
+            inherit_vars: target.inherit_vars,
+            env: target
+                .env
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_str()))
+                .collect(),
+            cwd: target.cwd.as_deref(),
+            location: target.location.as_ref().map(DumpLocation::from),
+        })
+        .collect::<Vec<_>>();
+
+    serde_json::to_string_pretty(&Dump {
+        version: RMAKE_DUMP_VERSION,
+        variables,
+        exports: &rmake.exports,
+        targets,
+    })
+}
@@ -0,0 +1,316 @@
+use crate::rmake::rmake::{RMake, RMakeOrigin, RMakeTarget};
+use std::collections::BTreeMap;
+
+/// Render the environment of a target as a shell prefix for its commands
+///
+/// Values are single quoted, so only make or ninja expand them.
+///
+/// # Arguments:
+///
+/// * env - The environment variables by name
+///
+/// Returns the prefix, empty if there is no variable
+fn env_prefix(env: &BTreeMap<String, String>) -> String {
+    if env.is_empty() {
+        return String::new();
+    }
+    let assignments = env
+        .iter()
+        .map(|(name, value)| format!("{}='{}'", name, value.replace('\'', "'\\''")))
+        .collect::<Vec<_>>();
+    format!("export {}; ", assignments.join(" "))
+}
+
+/// Render the loaded RMake object as an equivalent GNU Makefile
+///
+/// Variables and commands are written unexpanded, since GNU make expands
+/// them the same way. Targets keep the file order, so the default goal is
+/// the same.
+///
+/// # Arguments:
+///
+/// * rmake - The loaded RMake object
+///
+/// Returns the Makefile content
+pub fn render_make(rmake: &RMake) -> String {
+    let mut out = format!("# Generated by rmake from {}\n\n", rmake.files.join(", "));
+
+    /* Automatic variables like CURDIR are set by make itself */
+    let variables = rmake
+        .sorted_variables()
+        .into_iter()
+        .filter(|(_, variable)| variable.origin != RMakeOrigin::Automatic)
+        .collect::<Vec<_>>();
+    for (name, variable) in variables.iter() {
+        if variable.origin == RMakeOrigin::CommandLine {
+            out.push_str(&format!("override {} = {}\n", name, variable.value));
+        } else {
+            out.push_str(&format!("{} = {}\n", name, variable.value));
+        }
+    }
+    if !rmake.exports.is_empty() {
+        out.push_str(&format!("export {}\n", rmake.exports.join(" ")));
+    }
+    if !variables.is_empty() || !rmake.exports.is_empty() {
+        out.push('\n');
+    }
+
+    let targets = rmake
+        .order
+        .iter()
+        .filter_map(|name| rmake.targets.get(name))
+        .collect::<Vec<_>>();
+
+    /* Targets named differently from their outputs are aliases, like phony ones */
+    let phony = targets
+        .iter()
+        .filter(|target| target.phony || !target.outputs.contains(&target.name))
+        .map(|target| target.name.as_str())
+        .collect::<Vec<_>>();
+    if !phony.is_empty() {
+        out.push_str(&format!(".PHONY: {}\n\n", phony.join(" ")));
+    }
+
+    for target in targets.iter() {
+        if let Some(desc) = &target.desc {
+            out.push_str(&format!("# {}\n", desc));
+        }
+
+        let deps = target.deps.as_deref().unwrap_or_default().join(" ");
+        let outputs = target.outputs.join(" ");
+        let grouped = !target.phony && target.outputs != [target.name.clone()];
+
+        /*
+         *   Target-specific variables, private ones are not inherited by prerequisites
+         *   They are set on the rule running the commands, the outputs of a grouped one
+         */
+        let mut vars = target.vars.iter().collect::<Vec<_>>();
+        vars.sort();
+        for (name, value) in vars {
+            let private = if target.inherit_vars { "" } else { "private " };
+            let names = if grouped { &outputs } else { &target.name };
+            out.push_str(&format!("{}: {}{} = {}\n", names, private, name, value));
+        }
+
+        if !grouped {
+            out.push_str(format!("{}: {}", target.name, deps).trim_end());
+        } else {
+            if !target.outputs.contains(&target.name) {
+                out.push_str(&format!("{}: {}\n", target.name, outputs));
+            }
+            out.push_str(format!("{} &: {}", outputs, deps).trim_end());
+        }
+        out.push('\n');
+
+        /* Target-specific exports would leak to prerequisites, set them in each line */
+        let mut prefix = env_prefix(&target.raw_env.clone().into_iter().collect());
+        if let Some(cwd) = &target.cwd {
+            prefix.push_str(&format!("cd {} && ", cwd));
+        }
+        for cmd in target.raw_cmds.iter().filter(|cmd| !cmd.trim().is_empty()) {
+            /* $@, $^ and $< of make are relative to the Makefile, not to the cwd */
+            let cmd = match &target.cwd {
+                Some(_) => target.expand_automatic(cmd, &rmake.root),
+                None => cmd.clone(),
+            };
+            out.push_str(&format!("\t{}{}\n", prefix, cmd));
+        }
+        out.push('\n');
+    }
+
+    /* Implicit dependencies discovered by the compiler */
+    let depfiles = targets
+        .iter()
+        .filter_map(|target| target.depfile.as_deref())
+        .collect::<Vec<_>>();
+    if !depfiles.is_empty() {
+        out.push_str(&format!("-include {}\n", depfiles.join(" ")));
+    }
+
+    out
+}
+
+/// Escape a path to be used in a ninja build line
+///
+/// # Arguments:
+///
+/// * path - The path to escape
+fn ninja_path(path: &str) -> String {
+    path.replace('$', "$$")
+        .replace(' ', "$ ")
+        .replace(':', "$:")
+}
+
+/// Translate an expanded RMake command to ninja, mapping $@, $^ and $< to
+/// ${target_name}, ${in} and ${in_first}, and escaping any other $
+///
+/// $@ is the target name in RMake, not all the outputs like $out. The
+/// braces stop the names, ninja would read $target_name-old as one variable.
+///
+/// # Arguments:
+///
+/// * cmd - The command with variables already expanded
+fn ninja_command(cmd: &str) -> String {
+    let mut out = String::new();
+    let mut chars = cmd.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            out.push(c);
+            continue;
+        }
+        match chars.peek() {
+            Some('@') => out.push_str("${target_name}"),
+            Some('^') => out.push_str("${in}"),
+            Some('<') => out.push_str("${in_first}"),
+            _ => {
+                out.push_str("$$");
+                continue;
+            }
+        }
+        chars.next();
+    }
+    out
+}
+
+/// Name of the ninja rule of a target
+///
+/// # Arguments:
+///
+/// * target - The RMakeTarget
+/// * index - The index of the target in file order, keeps names like main.o
+///   and main_o apart
+fn ninja_rule(target: &RMakeTarget, index: usize) -> String {
+    let name = target
+        .name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+    format!("rmake_{}_{}", name, index)
+}
+
+/// Render the loaded RMake object as a ninja build file
+///
+/// Each target gets its own rule running its commands, and a build edge
+/// from its dependencies to its outputs.
+///
+/// # Arguments:
+///
+/// * rmake - The loaded RMake object
+///
+/// Returns the build.ninja content
+pub fn render_ninja(rmake: &RMake) -> String {
+    let mut out = format!(
+        "# Generated by rmake from {}\n\nninja_required_version = 1.3\n",
+        rmake.files.join(", ")
+    );
+
+    let targets = rmake
+        .order
+        .iter()
+        .filter_map(|name| rmake.targets.get(name))
+        .collect::<Vec<_>>();
+
+    for (index, target) in targets.iter().enumerate() {
+        let cmds = target
+            .raw_cmds
+            .iter()
+            .filter(|cmd| !cmd.trim().is_empty())
+            .map(|cmd| {
+                let cmd = rmake.expand_variables(cmd, target);
+                match &target.cwd {
+                    Some(_) => ninja_command(&target.expand_automatic(&cmd, &rmake.root)),
+                    None => ninja_command(&cmd),
+                }
+            })
+            .collect::<Vec<_>>();
+        let deps = target.deps.as_deref().unwrap_or_default();
+        let outputs = if target.phony {
+            vec![target.name.clone()]
+        } else {
+            target.outputs.clone()
+        };
+
+        out.push_str(&format!("\nrule {}\n", ninja_rule(target, index)));
+        if cmds.is_empty() {
+            out.push_str("  command = true\n");
+        } else {
+            let mut prefix = env_prefix(&rmake.command_env(target));
+            if let Some(cwd) = &target.cwd {
+                prefix.push_str(&format!("cd {} && ", cwd));
+            }
+            out.push_str(&format!(
+                "  command = {}{}\n",
+                ninja_command(&prefix),
+                cmds.join(" && ")
+            ));
+        }
+        out.push_str(&format!(
+            "  description = {}\n",
+            ninja_command(&target.name)
+        ));
+        if let Some(depfile) = &target.depfile {
+            out.push_str(&format!(
+                "  depfile = {}\n  deps = gcc\n",
+                ninja_command(depfile)
+            ));
+        }
+
+        out.push_str(&format!(
+            "build {}: {}",
+            outputs
+                .iter()
+                .map(|o| ninja_path(o))
+                .collect::<Vec<_>>()
+                .join(" "),
+            ninja_rule(target, index)
+        ));
+        for dep in deps {
+            out.push_str(&format!(" {}", ninja_path(dep)));
+        }
+        out.push('\n');
+        out.push_str(&format!("  target_name = {}\n", ninja_path(&target.name)));
+        if let Some(first) = deps.first() {
+            out.push_str(&format!("  in_first = {}\n", ninja_command(first)));
+        }
+
+        /* Targets named differently from their outputs are aliases */
+        if !target.phony && !target.outputs.contains(&target.name) {
+            out.push_str(&format!(
+                "build {}: phony {}\n",
+                ninja_path(&target.name),
+                outputs
+                    .iter()
+                    .map(|o| ninja_path(o))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            ));
+        }
+    }
+
+    if let Some(first) = targets.first() {
+        out.push_str(&format!("\ndefault {}\n", ninja_path(&first.name)));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ninja_command_braces_automatic_variables() {
+        assert_eq!(
+            ninja_command("cp $@ $@-x"),
+            "cp ${target_name} ${target_name}-x"
+        );
+        assert_eq!(
+            ninja_command("cc -MF $@.d -c $< $^"),
+            "cc -MF ${target_name}.d -c ${in_first} ${in}"
+        );
+    }
+
+    #[test]
+    fn ninja_command_escapes_other_dollars() {
+        assert_eq!(ninja_command("echo $$ $HOME"), "echo $$$$ $$HOME");
+    }
+}
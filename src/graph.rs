@@ -0,0 +1,105 @@
+use crate::rmake::rmake::RMake;
+use crate::state::RMakeState;
+use std::collections::HashSet;
+
+/// Quote a String to be used as a DOT identifier or label
+///
+/// # Arguments:
+///
+/// * value - The String to quote
+fn quote(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+    format!("\"{}\"", escaped)
+}
+
+/// Render the dependency graph of the requested goals in Graphviz DOT format
+///
+/// Targets are boxes, stale ones are filled in red and up to date ones in
+/// green, phony targets are dashed. Files that are not produced by any target
+/// are ellipses, implicit dependencies from depfiles are dashed edges.
+///
+/// # Arguments:
+///
+/// * rmake - The loaded RMake object
+/// * names - The goal names, the first target is used if empty
+///
+/// Returns the DOT content
+pub fn render(rmake: &RMake, names: &[String]) -> String {
+    let state = RMakeState::load(&rmake.state_path());
+    let mut nodes = vec![];
+    let mut edges = vec![];
+    let mut files = HashSet::new();
+
+    for (target, reason) in rmake.plan(names) {
+        let style = if target.phony {
+            "style=\"dashed\""
+        } else if reason.is_some() {
+            "style=\"filled\", fillcolor=\"lightsalmon\""
+        } else {
+            "style=\"filled\", fillcolor=\"palegreen\""
+        };
+
+        /* Grouped targets show all their outputs */
+        let mut label = target.name.clone();
+        if target.outputs.len() > 1 || target.outputs[0] != target.name {
+            label.push_str(&format!("\n[{}]", target.outputs.join(", ")));
+        }
+        if let Some(reason) = &reason {
+            label.push_str(&format!("\n({})", reason));
+        }
+        nodes.push(format!(
+            "    {} [shape=box, label={}, {}];",
+            quote(&target.name),
+            quote(&label),
+            style
+        ));
+
+        for dep in target.deps.iter().flatten() {
+            match rmake.get_target(dep) {
+                Some(dep_target) if dep_target.name != *dep => edges.push(format!(
+                    "    {} -> {} [label={}];",
+                    quote(&target.name),
+                    quote(&dep_target.name),
+                    quote(dep)
+                )),
+                Some(dep_target) => edges.push(format!(
+                    "    {} -> {};",
+                    quote(&target.name),
+                    quote(&dep_target.name)
+                )),
+                None => {
+                    files.insert(dep.clone());
+                    edges.push(format!("    {} -> {};", quote(&target.name), quote(dep)));
+                }
+            }
+        }
+
+        if let Some(recorded) = state.targets.get(&target.name) {
+            for dep in recorded.implicit_deps.iter() {
+                files.insert(dep.clone());
+                edges.push(format!(
+                    "    {} -> {} [style=\"dashed\"];",
+                    quote(&target.name),
+                    quote(dep)
+                ));
+            }
+        }
+    }
+
+    let mut files = files.into_iter().collect::<Vec<_>>();
+    files.sort();
+    for file in files {
+        nodes.push(format!("    {} [shape=ellipse];", quote(&file)));
+    }
+
+    let mut dot = String::from("digraph rmake {\n    rankdir=\"LR\";\n");
+    for line in nodes.iter().chain(edges.iter()) {
+        dot.push_str(line);
+        dot.push('\n');
+    }
+    dot.push_str("}\n");
+    dot
+}
@@ -0,0 +1,34 @@
+use crate::rmake::rmake::RMake;
+
+/// Render the list of available targets with their descriptions
+///
+/// Targets are listed in file order, hidden ones are skipped.
+///
+/// # Arguments:
+///
+/// * rmake - The loaded RMake object
+///
+/// Returns the list content
+pub fn render(rmake: &RMake) -> String {
+    let targets = rmake
+        .order
+        .iter()
+        .filter_map(|name| rmake.targets.get(name))
+        .filter(|target| !target.hidden)
+        .collect::<Vec<_>>();
+
+    let width = targets.iter().map(|t| t.name.len()).max().unwrap_or(0);
+    let mut out = String::new();
+    for target in targets {
+        let mut line = format!("{:width$}", target.name, width = width);
+        if let Some(desc) = &target.desc {
+            line.push_str(&format!("  {}", desc));
+        }
+        if let Some(deps) = &target.deps {
+            line.push_str(&format!("  [deps: {}]", deps.join(", ")));
+        }
+        out.push_str(line.trim_end());
+        out.push('\n');
+    }
+    out
+}
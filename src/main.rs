@@ -1,10 +1,21 @@
+mod compdb;
+mod convert;
+mod database;
+mod dump;
+mod export;
+mod graph;
+mod list;
 mod rmake;
+mod state;
+mod validate;
 
+use rmake::rmake::{RMake, RMAKE_FILE_NAMES};
 use std::fs::metadata;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use structopt::clap::AppSettings;
 use structopt::StructOpt;
 use tracing::{debug, error, info, Level};
-use tracing_subscriber;
 
 #[macro_export]
 macro_rules! RMakeError {
@@ -15,12 +26,148 @@ macro_rules! RMakeError {
 }
 
 #[derive(StructOpt)]
+/* Without AllowExternalSubcommands, clap rejects `rmake -- convert` instead of building the target */
+#[structopt(
+    setting = AppSettings::ArgsNegateSubcommands,
+    setting = AppSettings::AllowExternalSubcommands
+)]
 struct RMakeArgs {
-    #[structopt(help = "Target")]
-    target: Option<String>,
+    #[structopt(
+        help = "Targets to build, the first target is used if none is given. NAME=value overrides a variable"
+    )]
+    targets: Vec<String>,
 
-    #[structopt(long = "--directory", short = "-C", default_value = "./")]
+    #[structopt(
+        long = "--directory",
+        short = "-C",
+        default_value = "./",
+        global = true
+    )]
     directory: String,
+
+    #[structopt(
+        long = "--file",
+        short = "-f",
+        help = "RMakefile to read, relative to the build directory. Repeat it to merge several files in order",
+        number_of_values = 1,
+        global = true
+    )]
+    files: Vec<String>,
+
+    #[structopt(
+        long = "--hash",
+        help = "Rebuild only when the content of inputs or commands changed"
+    )]
+    hash: bool,
+
+    #[structopt(long = "--explain", help = "Print why each target is rebuilt")]
+    explain: bool,
+
+    #[structopt(
+        short = "-d",
+        help = "Debug flags, \"why\" is the same as --explain",
+        possible_values = &["why"],
+        number_of_values = 1
+    )]
+    debug: Vec<String>,
+
+    #[structopt(
+        long = "--question",
+        short = "-q",
+        help = "Run nothing, exit with 0 if the targets are up to date, 1 otherwise"
+    )]
+    question: bool,
+
+    #[structopt(
+        long = "--touch",
+        short = "-t",
+        help = "Touch the outputs of stale targets instead of running their commands"
+    )]
+    touch: bool,
+
+    #[structopt(
+        long = "--dry-run",
+        short = "-n",
+        help = "Print the commands of stale targets without running them"
+    )]
+    dry_run: bool,
+
+    #[structopt(
+        long = "--always-make",
+        short = "-B",
+        help = "Consider all targets stale"
+    )]
+    always_make: bool,
+
+    #[structopt(
+        long = "--what-if",
+        short = "-W",
+        help = "Pretend the given file was just modified",
+        number_of_values = 1
+    )]
+    what_if: Vec<String>,
+
+    #[structopt(
+        long = "--graph",
+        help = "Print the dependency graph of the targets in Graphviz DOT format"
+    )]
+    graph: bool,
+
+    #[structopt(
+        long = "--dump-json",
+        help = "Print the loaded RMakefile as JSON, without running anything"
+    )]
+    dump_json: bool,
+
+    #[structopt(
+        long = "--print-data-base",
+        short = "-p",
+        help = "Print all variables and rules, without running anything"
+    )]
+    print_data_base: bool,
+
+    #[structopt(
+        long = "--list",
+        help = "List the available targets with their descriptions"
+    )]
+    list: bool,
+
+    #[structopt(
+        long = "--compdb",
+        help = "Print the compile_commands.json of the targets, without running anything"
+    )]
+    compdb: bool,
+
+    #[structopt(
+        long = "--generate",
+        help = "Generate the build file of another build system in the build directory",
+        possible_values = &["ninja"]
+    )]
+    generate: Option<String>,
+
+    #[structopt(subcommand)]
+    command: Option<RMakeCommand>,
+}
+
+/// Subcommands only apply before any target, `rmake -- convert` builds a target named convert
+#[derive(StructOpt)]
+enum RMakeCommand {
+    /// Convert a GNU Makefile to RMakefile.yml, printed on stdout
+    Convert {
+        #[structopt(help = "The Makefile path")]
+        makefile: String,
+    },
+
+    /// Export the RMakefile.yml to another build system, printed on stdout
+    Export {
+        #[structopt(
+            long = "--format",
+            help = "The output format",
+            possible_values = &["make"],
+            default_value = "make"
+        )]
+        format: String,
+    },
 }
 
 fn main() {
@@ -34,6 +181,7 @@ fn main() {
         tracing_subscriber::fmt()
             .with_target(false)
             .compact()
+            .with_writer(std::io::stderr)
             .with_max_level(Level::from_str(&log_l).unwrap())
             .finish(),
     )
@@ -42,23 +190,137 @@ fn main() {
     /* Parse arguments */
     let rmake_args = RMakeArgs::from_args();
     let dir = rmake_args.directory;
+    if let Some(RMakeCommand::Convert { makefile }) = &rmake_args.command {
+        match convert::convert(makefile) {
+            Ok(yml) => print!("{}", yml),
+            Err(e) => {
+                RMakeError!("Cannot convert {}: {}", makefile, e);
+            }
+        }
+        return;
+    }
 
     /* Check if given directory is directory */
-    if !metadata(&dir).unwrap().is_dir() {
+    if !metadata(&dir).is_ok_and(|m| m.is_dir()) {
         RMakeError!("Path is not a directory: {}", dir);
     }
 
-    /* Change current working directory */
-    info!("Setting build directory ..");
-    std::env::set_current_dir(&dir).expect(format!("Cannot change directory to: {}", dir).as_str());
+    /* Paths are resolved from the build directory, the process one is kept */
+    debug!("Build directory: {}", dir);
 
-    debug!("Current dir: {:?}", std::env::current_dir().unwrap());
+    /* Split NAME=value variable overrides from targets */
+    let (overrides, targets): (Vec<String>, Vec<String>) = rmake_args
+        .targets
+        .into_iter()
+        .partition(|arg| arg.contains('='));
+    let overrides = overrides
+        .into_iter()
+        .map(|arg| {
+            let (name, value) = arg.split_once('=').unwrap();
+            (name.to_string(), value.to_string())
+        })
+        .collect();
 
-    let rmake = rmake::rmake::RMake::new("RMakefile.yml".to_string());
+    /* Without -f, the nearest RMakefile with a default name is used */
+    let (root, files) = if rmake_args.files.is_empty() {
+        match RMake::find_root(Path::new(&dir)) {
+            Some((root, file)) => {
+                if std::fs::canonicalize(&dir).is_ok_and(|start| start != root) {
+                    info!("Using {} in {}", file, root.display());
+                } else {
+                    debug!("Using {} in {}", file, root.display());
+                }
+                (root, vec![file])
+            }
+            None => {
+                RMakeError!(
+                    "No RMakefile found in {} or any parent directory, looked for {}",
+                    dir,
+                    RMAKE_FILE_NAMES.join(", ")
+                );
+            }
+        }
+    } else {
+        (PathBuf::from(&dir), rmake_args.files)
+    };
+
+    /* Like the build root, the directory RMake was started from is given to RMake */
+    let invocation_dir = match std::env::current_dir() {
+        Ok(invocation_dir) => invocation_dir,
+        Err(e) => {
+            RMakeError!("Cannot get the current directory: {}", e);
+        }
+    };
+
+    let rmake = RMake::new(root, invocation_dir, files, overrides);
     match rmake {
-        Ok(mut rm) => rm.run(rmake_args.target),
+        Ok(mut rm) => {
+            rm.options.hash = rmake_args.hash;
+            rm.options.touch = rmake_args.touch;
+            rm.options.dry_run = rmake_args.dry_run;
+            rm.options.always_make = rmake_args.always_make;
+            rm.options.what_if = rmake_args.what_if;
+            rm.options.explain =
+                rmake_args.explain || rmake_args.debug.iter().any(|flag| flag == "why");
+            if rmake_args.compdb {
+                match compdb::render(&rm, &targets) {
+                    Ok(json) => println!("{}", json),
+                    Err(e) => {
+                        RMakeError!("Cannot generate the compilation database: {}", e);
+                    }
+                }
+                return;
+            }
+            if let Some(RMakeCommand::Export { format }) = &rmake_args.command {
+                match format.as_str() {
+                    "make" => print!("{}", export::render_make(&rm)),
+                    _ => {
+                        RMakeError!("Unsupported export format: {}", format);
+                    }
+                }
+                return;
+            }
+            if let Some(backend) = &rmake_args.generate {
+                let (path, content) = match backend.as_str() {
+                    "ninja" => ("build.ninja", export::render_ninja(&rm)),
+                    _ => {
+                        RMakeError!("Unsupported generator: {}", backend);
+                    }
+                };
+                if let Err(e) = std::fs::write(rm.resolve(path), content) {
+                    RMakeError!("Cannot write {}: {}", path, e);
+                }
+                info!("Generated {}", path);
+                return;
+            }
+            if rmake_args.list {
+                print!("{}", list::render(&rm));
+                return;
+            }
+            if rmake_args.print_data_base {
+                print!("{}", database::render(&rm));
+                return;
+            }
+            if rmake_args.dump_json {
+                match dump::render(&rm) {
+                    Ok(json) => println!("{}", json),
+                    Err(e) => {
+                        RMakeError!("Cannot serialize the RMakefile: {}", e);
+                    }
+                }
+                return;
+            }
+            if rmake_args.graph {
+                print!("{}", graph::render(&rm, &targets));
+                return;
+            }
+            if rmake_args.question {
+                std::process::exit(if rm.question(targets) { 0 } else { 1 });
+            }
+            rm.run(targets)
+        }
         Err(e) => {
-            RMakeError!("Error loading RMakefile.yml file : {}", e);
+            RMakeError!("Error loading RMakefile: {}", e);
         }
     }
 }
@@ -1,9 +1,20 @@
 /// This represents the rmake utilities
+#[allow(clippy::module_inception)]
 pub mod rmake {
+    use crate::state::{self, RMakeState, RMakeTargetState, RMAKE_STATE_FILE};
+    use crate::validate::{self, RMakeSources};
     use crate::RMakeError;
     use serde_yaml::{Mapping, Value};
-    use std::{collections::HashMap, vec};
-    use tracing::{debug, error, info};
+    use std::collections::{BTreeMap, HashMap, HashSet};
+    use std::path::{Path, PathBuf};
+    use std::time::{Duration, Instant, SystemTime};
+    use tracing::{debug, error, info, warn};
+
+    /// The top-level key listing the variables exported to the commands
+    pub const RMAKE_EXPORT_KEY: &str = "export";
+
+    /// The RMakefile names looked for in the build directory, in order
+    pub const RMAKE_FILE_NAMES: [&str; 3] = ["RMakefile.yml", "RMakefile.yaml", "rmake.yml"];
 
     /// This represents a Core command that can be run
     pub enum RMakeCoreCommand {
@@ -39,7 +50,60 @@ pub mod rmake {
         _File(String),
 
         /// The dep is another target
-        _Target(RMakeTarget),
+        _Target(Box<RMakeTarget>),
+    }
+
+    /// This represents why a Target needs to be rebuilt
+    #[derive(Debug)]
+    pub enum RMakeReason {
+        /// The target is phony, it is always rebuilt
+        Phony,
+
+        /// An output of the target does not exist
+        MissingOutput(String),
+
+        /// A dependency was rebuilt in this run
+        RebuiltDep(String),
+
+        /// A dependency is newer than the oldest output
+        NewerDep(String),
+
+        /// An implicit dependency recorded from the depfile does not exist anymore
+        MissingImplicitDep(String),
+
+        /// The expanded commands changed since the last build
+        CommandChanged,
+
+        /// The content of an input changed since the last build, in hash mode
+        ChangedInput(String),
+
+        /// Nothing is recorded about the last build, in hash mode
+        NotRecorded,
+
+        /// All targets are considered stale
+        Forced,
+
+        /// A dependency is assumed to be modified
+        WhatIf(String),
+    }
+
+    impl std::fmt::Display for RMakeReason {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                Self::Phony => write!(f, "target is phony"),
+                Self::MissingOutput(output) => write!(f, "output {} is missing", output),
+                Self::RebuiltDep(dep) => write!(f, "dependency {} was rebuilt", dep),
+                Self::NewerDep(dep) => write!(f, "dependency {} is newer than output", dep),
+                Self::MissingImplicitDep(dep) => {
+                    write!(f, "implicit dependency {} is missing", dep)
+                }
+                Self::CommandChanged => write!(f, "command line changed"),
+                Self::ChangedInput(input) => write!(f, "content of {} changed", input),
+                Self::NotRecorded => write!(f, "no previous build recorded"),
+                Self::Forced => write!(f, "rebuild is forced"),
+                Self::WhatIf(dep) => write!(f, "dependency {} is assumed modified", dep),
+            }
+        }
     }
 
     /// This represents a Target
@@ -53,16 +117,111 @@ pub mod rmake {
 
         /// The list of commands that needs to be run on the target visit
         pub cmds: Vec<String>,
+
+        /// The list of commands as written in the RMakefile, before expansion
+        pub raw_cmds: Vec<String>,
+
+        /// The list of files produced by the commands, defaults to the target name
+        pub outputs: Vec<String>,
+
+        /// Optional Makefile-syntax file written by the commands listing implicit dependencies
+        pub depfile: Option<String>,
+
+        /// A phony target does not produce files, it is always rebuilt
+        pub phony: bool,
+
+        /// Where the target is defined
+        pub location: Option<RMakeLocation>,
+
+        /// Optional description shown by --list
+        pub desc: Option<String>,
+
+        /// A hidden target is not shown by --list
+        pub hidden: bool,
+
+        /// Variables overriding the global ones when expanding the commands of the target
+        pub vars: HashMap<String, String>,
+
+        /// Expand the commands of the prerequisites with the vars of the target too
+        pub inherit_vars: bool,
+
+        /// The depfile path as written in the RMakefile, before expansion
+        pub raw_depfile: Option<String>,
+
+        /// Environment variables set for every command of the target, expanded
+        pub env: HashMap<String, String>,
+
+        /// The environment variables as written in the RMakefile, before expansion
+        pub raw_env: HashMap<String, String>,
+
+        /// The directory the commands run in, relative to the RMakefile
+        pub cwd: Option<String>,
+    }
+
+    /// This represents where an entry is defined in the RMakefile
+    #[derive(Debug, Clone)]
+    pub struct RMakeLocation {
+        /// The RMakefile path
+        pub file: String,
+
+        /// The line number, starting at 1
+        pub line: usize,
+
+        /// The column number, starting at 1
+        pub column: usize,
+    }
+
+    impl std::fmt::Display for RMakeLocation {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{}:{}:{}", self.file, self.line, self.column)
+        }
+    }
+
+    /// This represents where the value of a Variable comes from
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum RMakeOrigin {
+        /// Defined in the RMakefile
+        File,
+
+        /// Inherited from the environment
+        Environment,
+
+        /// Given as NAME=value on the command line, overrides the RMakefile
+        CommandLine,
+
+        /// Set by RMake for each target, like $@
+        Automatic,
+
+        /// Defined in the vars of a target, overrides the RMakefile for that target
+        Target,
+    }
+
+    impl std::fmt::Display for RMakeOrigin {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                Self::File => write!(f, "file"),
+                Self::Environment => write!(f, "environment"),
+                Self::CommandLine => write!(f, "command line"),
+                Self::Automatic => write!(f, "automatic"),
+                Self::Target => write!(f, "target-specific"),
+            }
+        }
     }
 
     /// This represents a Variable
-    #[derive(Debug)]
+    #[derive(Debug, Clone)]
     pub struct RMakeVariable {
         /// The name of the variable, issued from a String YAML Value
         pub name: String,
 
-        /// The value of the variable, MUST be a String, though YAML supports more
+        /// The value of the variable, YAML scalars and sequences are converted to a String
         pub value: String,
+
+        /// Where the variable is defined
+        pub location: Option<RMakeLocation>,
+
+        /// Where the value comes from
+        pub origin: RMakeOrigin,
     }
 
     impl RMakeVariable {
@@ -73,30 +232,111 @@ pub mod rmake {
         /// * name - The name of the variable
         /// * value - The YAML Value object
         ///
-        /// Returns an Option indicating the Value is string or not
+        /// Scalars are converted to their textual form and sequences are
+        /// joined with spaces, nested sequences are flattened and other items
+        /// are dropped (validate::validate warns about them).
+        ///
+        /// Returns an Option indicating the Value can be converted or not
         pub fn from_value(name: String, value: &Value) -> Option<RMakeVariable> {
-            if value.is_string() {
-                return Some(RMakeVariable {
-                    name: name,
-                    value: value.as_str().unwrap().to_string(),
-                });
+            /// Convert a Value to text, None for mappings and tagged values
+            fn to_text(value: &Value) -> Option<String> {
+                match value {
+                    Value::String(s) => Some(s.clone()),
+                    Value::Number(n) => Some(n.to_string()),
+                    Value::Bool(b) => Some(b.to_string()),
+                    Value::Null => Some(String::new()),
+                    Value::Sequence(seq) => Some(
+                        seq.iter()
+                            .filter(|item| !item.is_null())
+                            .filter_map(to_text)
+                            .collect::<Vec<_>>()
+                            .join(" "),
+                    ),
+                    Value::Mapping(_) | Value::Tagged(_) => None,
+                }
             }
-            None
+
+            to_text(value).map(|value| RMakeVariable {
+                name,
+                value,
+                location: None,
+                origin: RMakeOrigin::File,
+            })
+        }
+
+        /// Expand the value of the variable
+        ///
+        /// # Arguments:
+        ///
+        /// * variables - Optional list of all variables of the YAML file
+        /// * root - The build root, where shell commands run
+        ///
+        /// Returns the expanded value
+        pub fn expand(&self, variables: &Option<RMakeVariables>, root: &Path) -> String {
+            RMakeUtils::find_and_replace(
+                self.value.clone(),
+                RMakeUtils::rmake_re_var(),
+                variables,
+                &self.name,
+                &None,
+                root,
+            )
         }
     }
 
     /// Defining custom types
     type RMakeTargets = HashMap<String, RMakeTarget>;
-    type RMakeVariables = HashMap<String, RMakeVariable>;
+    pub type RMakeVariables = HashMap<String, RMakeVariable>;
+    type RMakeProducers = HashMap<String, String>;
+
+    /// This represents the options changing how targets are built
+    #[derive(Debug, Default)]
+    pub struct RMakeOptions {
+        /// Compare digests of inputs and commands instead of modification times
+        pub hash: bool,
+
+        /// Log why each target is rebuilt
+        pub explain: bool,
+
+        /// Update the modification time of stale outputs instead of running commands
+        pub touch: bool,
+
+        /// Print the commands of stale targets instead of running them
+        pub dry_run: bool,
+
+        /// Consider all targets stale
+        pub always_make: bool,
+
+        /// Files assumed to be just modified
+        pub what_if: Vec<String>,
+    }
 
     /// This represents the main object of RMake project
     #[derive(Debug)]
     pub struct RMake {
+        /// The build root, every relative path and command is resolved from it
+        pub root: PathBuf,
+
+        /// The RMakefiles that were loaded, relative to the build root
+        pub files: Vec<String>,
+
         /// List of targets of the YAML file
         pub targets: RMakeTargets,
 
         /// List of variables of the YAML file, this is Option because you can have no variables
         pub variables: Option<RMakeVariables>,
+
+        /// Map of every declared output file to the name of the target producing it
+        pub producers: RMakeProducers,
+
+        /// Names of the targets in file order
+        pub order: Vec<String>,
+
+        /// Variables of the RMakefile exported to the environment of every command
+        pub exports: Vec<String>,
+
+        /// Options used when running the targets
+        pub options: RMakeOptions,
     }
 
     impl RMake {
@@ -106,39 +346,46 @@ pub mod rmake {
         ///
         /// * global_map - The global mapping for the YAML file
         ///
-        /// Returns a tuple of two Option of HashMaps for Targets and Variables
+        /// Returns a tuple of two Option of HashMaps for Targets and Variables,
+        /// and the target names in file order
         fn extract_targets_and_variables(
             global_map: &Mapping,
-        ) -> (Option<RMakeTargets>, Option<RMakeVariables>) {
+        ) -> (Option<RMakeTargets>, Option<RMakeVariables>, Vec<String>) {
             let mut inner_targets = HashMap::new();
             let mut inner_variables = HashMap::new();
+            let mut order = vec![];
 
             for (key, val) in global_map {
                 let key_name = key.as_str().unwrap().to_string();
+                if key_name == RMAKE_EXPORT_KEY {
+                    /* Not a variable, it lists the exported ones */
+                    continue;
+                }
                 if val.is_mapping() {
+                    order.push(key_name.clone());
                     inner_targets.insert(
                         key_name.clone(),
                         RMakeTarget::from_mapping(key_name, val.as_mapping().unwrap()),
                     );
                 } else {
-                    let var_value = RMakeVariable::from_value(key_name.clone(), val);
-                    if var_value.is_some() {
-                        inner_variables.insert(key_name.clone(), var_value.unwrap());
+                    if let Some(var_value) = RMakeVariable::from_value(key_name.clone(), val) {
+                        inner_variables.insert(key_name.clone(), var_value);
                     }
                 }
             }
 
             (
-                if inner_targets.len() > 0 {
+                if !inner_targets.is_empty() {
                     Some(inner_targets)
                 } else {
                     None
                 },
-                if inner_variables.len() > 0 {
+                if !inner_variables.is_empty() {
                     Some(inner_variables)
                 } else {
                     None
                 },
+                order,
             )
         }
 
@@ -146,65 +393,395 @@ pub mod rmake {
         ///
         /// # Arguments:
         ///
-        /// * path - The RMakefile.yml path
+        /// * root - The build root directory
+        /// * invocation_dir - The absolute directory RMake was started from
+        /// * files - The RMakefile paths, relative to the build root, merged in order
+        /// * overrides - NAME=value variables given on the command line
         ///
         /// Returns a Result Self object
-        pub fn new(path: String) -> Result<RMake, String> {
-            match RMake::load_yml(path) {
-                Ok(yml_c) => {
-                    /* Content MUST be Mapping */
-                    if !yml_c.is_mapping() {
-                        return Err(String::from(
-                            "The Yml file should be Mapping, check the format!",
-                        ));
+        pub fn new(
+            root: PathBuf,
+            invocation_dir: PathBuf,
+            files: Vec<String>,
+            overrides: Vec<(String, String)>,
+        ) -> Result<RMake, String> {
+            /* Absolute, so it stays valid whatever the process directory is */
+            let root = std::fs::canonicalize(&root)
+                .map_err(|e| format!("Cannot use build directory {}: {}", root.display(), e))?;
+
+            match RMake::load_files(&root, &files) {
+                Ok((mapping, sources)) => {
+                    let mapping = &mapping;
+
+                    /* Report errors and warnings with their locations before extracting */
+                    let mut errors = vec![];
+                    for diagnostic in validate::validate(mapping, &sources) {
+                        if diagnostic.is_error {
+                            errors.push(diagnostic.to_string());
+                        } else {
+                            warn!("{}", diagnostic);
+                        }
+                    }
+                    if !errors.is_empty() {
+                        return Err(errors.join("\n"));
                     }
-
-                    /* We are sure that this is Mapping, so unwrap is safe here !*/
-                    let mapping = yml_c.as_mapping().unwrap();
 
                     /* Extract all Mappings and Variables */
-                    let (targets, variables) = RMake::extract_targets_and_variables(mapping);
+                    let (targets, variables, order) = RMake::extract_targets_and_variables(mapping);
 
                     if targets.is_none() {
                         return Err(String::from("No target is defined in the input file!"));
                     }
 
                     let mut targets = targets.unwrap();
+                    let mut variables = variables;
+
+                    let exports = match mapping.get(RMAKE_EXPORT_KEY) {
+                        Some(Value::String(name)) => vec![name.clone()],
+                        Some(Value::Sequence(names)) => names
+                            .iter()
+                            .filter_map(|name| name.as_str().map(String::from))
+                            .collect(),
+                        _ => vec![],
+                    };
+
+                    /* Like make, the directories are set unless the RMakefile defines them */
+                    for (name, dir) in [("CURDIR", &root), ("INVOCATION_DIR", &invocation_dir)] {
+                        variables
+                            .get_or_insert_with(HashMap::new)
+                            .entry(name.to_string())
+                            .or_insert(RMakeVariable {
+                                name: name.to_string(),
+                                value: dir.to_string_lossy().to_string(),
+                                location: None,
+                                origin: RMakeOrigin::Automatic,
+                            });
+                    }
+
+                    /* Command line variables take precedence over the file ones */
+                    for (name, value) in overrides {
+                        variables.get_or_insert_with(HashMap::new).insert(
+                            name.clone(),
+                            RMakeVariable {
+                                name,
+                                value,
+                                location: None,
+                                origin: RMakeOrigin::CommandLine,
+                            },
+                        );
+                    }
 
                     /* Expand commands */
                     for (name, mut target_obj) in targets.clone().into_iter() {
-                        target_obj.expand_commands(&variables);
+                        target_obj.expand_commands(&variables, &HashMap::new(), &root);
                         *targets.get_mut(&name).unwrap() = target_obj.clone();
                     }
 
-                    return Ok(RMake {
-                        targets: targets,
-                        variables: variables,
-                    });
+                    /* Attach source locations */
+                    for (name, target_obj) in targets.iter_mut() {
+                        target_obj.location = sources.locations.get(&(name.clone(), None)).cloned();
+                    }
+                    for (name, variable) in variables.iter_mut().flatten() {
+                        if variable.origin == RMakeOrigin::File {
+                            variable.location =
+                                sources.locations.get(&(name.clone(), None)).cloned();
+                        }
+                    }
+
+                    /* Map each output to its target, so grouped outputs share one recipe */
+                    let mut producers = HashMap::new();
+                    for (name, target_obj) in targets.iter() {
+                        for output in target_obj.outputs.iter() {
+                            if let Some(other) = producers.insert(output.clone(), name.clone()) {
+                                return Err(format!(
+                                    "Output {} is produced by both {} and {}",
+                                    output, other, name
+                                ));
+                            }
+                        }
+                    }
+
+                    Ok(RMake {
+                        root,
+                        files,
+                        targets,
+                        variables,
+                        producers,
+                        order,
+                        exports,
+                        options: RMakeOptions::default(),
+                    })
                 }
 
-                Err(e) => Err(format!("{}", e)),
+                Err(e) => Err(e),
             }
         }
 
+        /// Find the nearest RMakefile, trying RMAKE_FILE_NAMES in order in the
+        /// given directory then in each of its parents, like git does for .git
+        ///
+        /// # Arguments:
+        ///
+        /// * start - The directory to start from
+        ///
+        /// Returns the directory holding the RMakefile and the file name if any
+        pub fn find_root(start: &Path) -> Option<(PathBuf, String)> {
+            let start = std::fs::canonicalize(start).ok()?;
+            start.ancestors().find_map(|dir| {
+                RMAKE_FILE_NAMES
+                    .iter()
+                    .find(|name| dir.join(name).is_file())
+                    .map(|name| (dir.to_path_buf(), name.to_string()))
+            })
+        }
+
+        /// Load several RMakefiles and merge them in order
+        ///
+        /// Top-level entries of a later file replace the ones of an earlier
+        /// file, except the export lists that are concatenated.
+        ///
+        /// # Arguments:
+        ///
+        /// * root - The build root directory
+        /// * files - The RMakefile paths, relative to the build root
+        ///
+        /// Returns the merged Mapping, the locations of its keys and the written numbers of its variables
+        fn load_files(root: &Path, files: &[String]) -> Result<(Mapping, RMakeSources), String> {
+            let mut merged = Mapping::new();
+            let mut sources = RMakeSources::default();
+
+            for path in files {
+                let (content, yml_c) = RMake::load_yml(root.join(path))
+                    .map_err(|e| format!("Cannot load {}: {}", root.join(path).display(), e))?;
+
+                /* Content MUST be Mapping */
+                let mapping = match yml_c {
+                    Value::Mapping(mapping) => mapping,
+                    _ => {
+                        return Err(format!(
+                            "The Yml file {} should be Mapping, check the format!",
+                            path
+                        ))
+                    }
+                };
+
+                let file_sources = validate::locate(path, &content);
+                for (key, value) in mapping {
+                    let name = key.as_str().unwrap_or_default().to_string();
+                    let value = match (merged.get(&key), value) {
+                        (Some(Value::Sequence(exported)), Value::Sequence(more))
+                            if name == RMAKE_EXPORT_KEY =>
+                        {
+                            Value::Sequence(exported.iter().cloned().chain(more).collect())
+                        }
+                        (Some(previous), value) => {
+                            if previous.is_mapping() {
+                                warn!("{}: target {} overrides an earlier definition", path, name);
+                            } else {
+                                debug!("{}: {} overrides an earlier definition", path, name);
+                            }
+                            /* Forget where the replaced key and its own keys were */
+                            sources.locations.retain(|(located, _), _| *located != name);
+                            sources.numbers.retain(|located, _| located[0] != name);
+                            value
+                        }
+                        (None, value) => value,
+                    };
+                    merged.insert(key, value);
+                }
+
+                /* Keep the location of the first export list */
+                for (key, location) in file_sources.locations {
+                    if key.0 != RMAKE_EXPORT_KEY || !sources.locations.contains_key(&key) {
+                        sources.locations.insert(key, location);
+                    }
+                }
+                for (variable, written) in file_sources.numbers {
+                    sources.numbers.entry(variable).or_default().extend(written);
+                }
+            }
+            Ok((merged, sources))
+        }
+
         /// Load YAML content from a given file
         ///
         /// # Arguments:
         ///
         /// * path - The file path
         ///
-        /// Returns content String or Error on failure.
-        fn load_yml(path: String) -> Result<serde_yaml::Value, Box<dyn std::error::Error>> {
-            let reader = std::fs::File::open(path)?;
-            match serde_yaml::from_reader(reader) {
-                Ok(yml) => Ok(yml),
+        /// Returns the raw content String and the parsed YAML, or Error on failure.
+        fn load_yml(
+            path: PathBuf,
+        ) -> Result<(String, serde_yaml::Value), Box<dyn std::error::Error>> {
+            let content = std::fs::read_to_string(path)?;
+            match serde_yaml::from_str(&content) {
+                Ok(yml) => Ok((content, yml)),
                 Err(e) => Err(Box::new(e)),
             }
         }
 
-        #[allow(unused)]
-        fn get_target(&self, name: String) -> Option<&RMakeTarget> {
-            self.targets.get(&name)
+        /// Expand the variables of a given String, special characters like $@ are kept
+        ///
+        /// # Arguments:
+        ///
+        /// * value - The String to expand
+        /// * target - The RMakeTarget whose specific variables are used
+        ///
+        /// Returns the expanded String
+        pub fn expand_variables(&self, value: &str, target: &RMakeTarget) -> String {
+            RMakeUtils::find_and_replace(
+                value.to_string(),
+                RMakeUtils::rmake_re_var(),
+                &target.scoped_variables(&self.variables, &HashMap::new()),
+                &String::new(),
+                &None,
+                &self.root,
+            )
+        }
+
+        /// Resolve a path of the RMakefile from the build root
+        ///
+        /// # Arguments:
+        ///
+        /// * path - The path, relative to the build root or absolute
+        pub fn resolve(&self, path: &str) -> PathBuf {
+            self.root.join(path)
+        }
+
+        /// Sort key of a source location, to list entries in file order
+        ///
+        /// # Arguments:
+        ///
+        /// * location - The source location, None for entries not defined in a file
+        ///
+        /// Returns the index of the file in the loaded files and the line, None first
+        pub fn source_order(&self, location: Option<&RMakeLocation>) -> Option<(usize, usize)> {
+            location.map(|location| {
+                let file = self
+                    .files
+                    .iter()
+                    .position(|file| *file == location.file)
+                    .unwrap_or(self.files.len());
+                (file, location.line)
+            })
+        }
+
+        /// List the variables in file order, entries without a location first by name
+        ///
+        /// Returns the pairs of name and RMakeVariable
+        pub fn sorted_variables(&self) -> Vec<(&String, &RMakeVariable)> {
+            let mut variables = self.variables.iter().flatten().collect::<Vec<_>>();
+            variables.sort_by_key(|(name, variable)| {
+                (
+                    self.source_order(variable.location.as_ref()),
+                    name.to_string(),
+                )
+            });
+            variables
+        }
+
+        /// List the targets in file order, entries without a location first by name
+        ///
+        /// Returns a Vector of RMakeTarget references
+        pub fn sorted_targets(&self) -> Vec<&RMakeTarget> {
+            let mut targets = self.targets.values().collect::<Vec<_>>();
+            targets
+                .sort_by_key(|target| (self.source_order(target.location.as_ref()), &target.name));
+            targets
+        }
+
+        /// The path of the state file, in the build root
+        pub fn state_path(&self) -> PathBuf {
+            self.resolve(RMAKE_STATE_FILE)
+        }
+
+        /// Compute the environment variables set for the commands of a target
+        ///
+        /// Exported variables come first, the env of the target overrides them.
+        ///
+        /// # Arguments:
+        ///
+        /// * target - The RMakeTarget
+        ///
+        /// Returns the variables by name, sorted
+        pub fn command_env(&self, target: &RMakeTarget) -> BTreeMap<String, String> {
+            let mut env = BTreeMap::new();
+            let variables = target.scoped_variables(&self.variables, &HashMap::new());
+            for name in self.exports.iter() {
+                match variables.as_ref().and_then(|vars| vars.get(name)) {
+                    Some(variable) => {
+                        env.insert(name.clone(), variable.expand(&variables, &self.root));
+                    }
+                    None => debug!("Exported variable {} is not defined", name),
+                }
+            }
+            env.extend(target.env.clone());
+            env
+        }
+
+        /// Compute the digest of how a target is built, compared to the recorded one
+        ///
+        /// Like ninja, the environment is part of the command, so changing an
+        /// env or exported variable rebuilds the target. So is the cwd, the
+        /// same command line can do something else in another directory.
+        ///
+        /// # Arguments:
+        ///
+        /// * target - The RMakeTarget
+        ///
+        /// Returns the SHA-256 digest of the expanded commands, their environment and directory
+        pub fn command_digest(&self, target: &RMakeTarget) -> String {
+            let mut command = target.cmds.join("\n");
+            for (name, value) in self.command_env(target) {
+                command.push_str(&format!("\n{}={}", name, value));
+            }
+            if let Some(cwd) = &target.cwd {
+                command.push_str(&format!("\ncwd {}", self.resolve(cwd).display()));
+            }
+            state::digest(command.as_bytes())
+        }
+
+        /// Compute the current state of a target, the input digests are only read in hash mode
+        ///
+        /// # Arguments:
+        ///
+        /// * target - The RMakeTarget
+        /// * implicit_deps - The dependencies discovered from its depfile
+        fn target_state(&self, target: &RMakeTarget, implicit_deps: &[String]) -> RMakeTargetState {
+            let mut target_state =
+                RMakeTargetState::new(self.command_digest(target), implicit_deps);
+            if self.options.hash {
+                target_state.hash_inputs(target, &self.root);
+            }
+            target_state
+        }
+
+        /// Write the state file, failing to do so only costs rebuilds
+        ///
+        /// # Arguments:
+        ///
+        /// * state - The recorded state of the builds
+        fn save_state(&self, state: &RMakeState) {
+            if let Err(e) = state.save(&self.state_path()) {
+                warn!("Cannot write state file {}: {}", RMAKE_STATE_FILE, e);
+            }
+        }
+
+        /// Look up a target by its name or by one of its outputs
+        ///
+        /// # Arguments:
+        ///
+        /// * name - The target name or output file
+        ///
+        /// Returns the RMakeTarget if any
+        pub fn get_target(&self, name: &str) -> Option<&RMakeTarget> {
+            match self.targets.get(name) {
+                Some(target) => Some(target),
+                None => self
+                    .producers
+                    .get(name)
+                    .and_then(|target_name| self.targets.get(target_name)),
+            }
         }
 
         #[allow(unused)]
@@ -218,54 +795,190 @@ pub mod rmake {
             sum
         }
 
-        /// Chain all commands of all targets in order
+        /// Chain all targets in build order, each target appears once
         ///
         /// # Arguments:
         ///
         /// * main_target - The starting target
         ///
-        /// Returns a Vector of String
-        pub fn chain_commands(&mut self, main_target: RMakeTarget) -> Vec<String> {
+        /// Returns a Vector of RMakeTarget, dependencies first
+        pub fn chain_commands(&self, main_target: RMakeTarget) -> Vec<RMakeTarget> {
             /// Inner function to use it in recursive mode
             ///
             /// # Arguments:
             ///
             /// * target - The RMakeTarget to continue with
-            /// * targets - All RMakeTargets will be used to look for dependencies
-            /// * visited - A bool HashMap to mark that a Target is visited/found or not
+            /// * rmake - The RMake object used to look for dependencies
+            /// * visited - Set of target names that are already visited/found
+            /// * inherited - Target-specific variables inherited from the dependants
             ///
-            /// Returns a Vector of String that will accumulated recursively
+            /// Returns a Vector of RMakeTarget that will accumulated recursively
             fn find(
                 target: &RMakeTarget,
-                targets: &RMakeTargets,
-                visited: &mut HashMap<String, bool>,
-            ) -> Vec<String> {
-                let mut ret_command = vec![];
+                rmake: &RMake,
+                visited: &mut HashSet<String>,
+                inherited: &HashMap<String, String>,
+            ) -> Vec<RMakeTarget> {
+                let mut ret_targets = vec![];
+
+                /* Like make, the first dependant decides the inherited variables */
+                let mut target = target.clone();
+                if !inherited.is_empty() {
+                    target.expand_commands(&rmake.variables, inherited, &rmake.root);
+                }
+
+                let mut to_deps = inherited.clone();
+                if target.inherit_vars {
+                    to_deps.extend(target.vars.clone());
+                }
 
                 if let Some(dependencies) = &target.deps {
                     for dep in dependencies {
-                        if let Some(sub_target) = targets.get(dep) {
-                            if !visited.contains_key(dep) {
-                                visited.insert(dep.clone(), true);
-                                ret_command.extend(find(sub_target, targets, visited))
+                        if let Some(sub_target) = rmake.get_target(dep) {
+                            if visited.insert(sub_target.name.clone()) {
+                                ret_targets.extend(find(sub_target, rmake, visited, &to_deps))
                             }
                         }
                     }
                 }
 
-                ret_command.extend(target.cmds.clone());
-                ret_command
+                ret_targets.push(target);
+                ret_targets
+            }
+
+            let mut visited = HashSet::new();
+            visited.insert(main_target.name.clone());
+            find(&main_target, self, &mut visited, &HashMap::new())
+        }
+
+        /// Find why a target needs to be rebuilt
+        ///
+        /// Modification times are compared by default, in hash mode the
+        /// digests of the inputs are compared to the recorded state.
+        /// A command that changed since the last build always makes it stale.
+        ///
+        /// # Arguments:
+        ///
+        /// * target - The RMakeTarget to check
+        /// * rebuilt - Names of the targets that were rebuilt in this run
+        /// * state - The recorded state of the previous builds
+        ///
+        /// Returns the reason if the target is stale, None if it is up to date
+        fn rebuild_reason(
+            &self,
+            target: &RMakeTarget,
+            rebuilt: &HashSet<String>,
+            state: &RMakeState,
+        ) -> Option<RMakeReason> {
+            if target.phony {
+                return Some(RMakeReason::Phony);
+            }
+
+            if self.options.always_make {
+                return Some(RMakeReason::Forced);
+            }
+
+            /* The oldest output decides, a missing output is always stale */
+            let mut oldest_output: Option<SystemTime> = None;
+            for output in target.outputs.iter() {
+                match RMakeUtils::mtime(&self.resolve(output)) {
+                    Some(t) => {
+                        if oldest_output.is_none_or(|oldest| t < oldest) {
+                            oldest_output = Some(t);
+                        }
+                    }
+                    None => return Some(RMakeReason::MissingOutput(output.clone())),
+                }
+            }
+
+            let oldest_output = match oldest_output {
+                Some(t) => t,
+                None => return Some(RMakeReason::MissingOutput(target.name.clone())),
+            };
+
+            let mut newer_dep = None;
+            for dep in target.deps.iter().flatten() {
+                let dep_target = self.get_target(dep);
+                if let Some(dep_target) = dep_target {
+                    if rebuilt.contains(&dep_target.name) {
+                        return Some(RMakeReason::RebuiltDep(dep.clone()));
+                    }
+                }
+
+                if self.options.what_if.contains(dep) {
+                    return Some(RMakeReason::WhatIf(dep.clone()));
+                }
+
+                match RMakeUtils::mtime(&self.resolve(dep)) {
+                    Some(t) => {
+                        if t > oldest_output && newer_dep.is_none() {
+                            newer_dep = Some(RMakeReason::NewerDep(dep.clone()));
+                        }
+                    }
+                    None => {
+                        if dep_target.is_none() {
+                            RMakeError!(
+                                "No rule to make target {}, needed by {}",
+                                dep,
+                                target.name
+                            );
+                        }
+                    }
+                }
+            }
+
+            let recorded = match state.targets.get(&target.name) {
+                Some(recorded) => recorded,
+                None => {
+                    /* Nothing recorded yet, trust modification times unless hashing */
+                    if self.options.hash {
+                        return Some(RMakeReason::NotRecorded);
+                    }
+                    return newer_dep;
+                }
+            };
+
+            /* Implicit dependencies discovered from the depfile of the last build */
+            for dep in recorded.implicit_deps.iter() {
+                if self.options.what_if.contains(dep) {
+                    return Some(RMakeReason::WhatIf(dep.clone()));
+                }
+
+                match RMakeUtils::mtime(&self.resolve(dep)) {
+                    Some(t) => {
+                        if t > oldest_output && newer_dep.is_none() {
+                            newer_dep = Some(RMakeReason::NewerDep(dep.clone()));
+                        }
+                    }
+                    None => return Some(RMakeReason::MissingImplicitDep(dep.clone())),
+                }
+            }
+
+            let current = self.target_state(target, &recorded.implicit_deps);
+            if recorded.command != current.command {
+                return Some(RMakeReason::CommandChanged);
             }
 
-            let mut visited = HashMap::new();
-            let command_chain = find(&main_target, &self.targets, &mut visited);
-            command_chain
+            if self.options.hash {
+                for (input, digest) in current.inputs.iter() {
+                    if recorded.inputs.get(input) != Some(digest) {
+                        return Some(RMakeReason::ChangedInput(input.clone()));
+                    }
+                }
+                recorded
+                    .inputs
+                    .keys()
+                    .find(|input| !current.inputs.contains_key(*input))
+                    .map(|input| RMakeReason::ChangedInput(input.clone()))
+            } else {
+                newer_dep
+            }
         }
 
         pub fn get_first(&self, name: Option<String>) -> Option<&RMakeTarget> {
             match name {
-                Some(target_name) => self.targets.get(&target_name),
-                None => match self.targets.keys().next() {
+                Some(target_name) => self.get_target(&target_name),
+                None => match self.order.first() {
                     /* Return first target */
                     Some(key) => self.targets.get(key),
                     None => {
@@ -275,24 +988,208 @@ pub mod rmake {
             }
         }
 
-        /// Run the RMake system
+        /// Chain the targets of all the requested goals in build order
         ///
         /// # Arguments:
         ///
-        /// * name - The target name
-        pub fn run(&mut self, name: Option<String>) {
-            let first_target = self.get_first(name.clone());
-            if let Some(main_target) = first_target {
-                for cmd in self.chain_commands(main_target.clone()) {
-                    info!("Running: {}", cmd);
-                    let split_cmd = cmd.split_whitespace().collect::<Vec<_>>();
-                    std::process::Command::new(split_cmd[0])
-                        .args(&split_cmd[1..])
-                        .status()
-                        .expect(format!("Cannot run command: {}", cmd).as_str());
+        /// * names - The goal names, the first target is used if empty
+        ///
+        /// Returns a Vector of RMakeTarget, each target appears once
+        pub(crate) fn build_order(&self, names: &[String]) -> Vec<RMakeTarget> {
+            let goals = if names.is_empty() {
+                vec![None]
+            } else {
+                names.iter().cloned().map(Some).collect()
+            };
+
+            let mut seen = HashSet::new();
+            let mut order = vec![];
+            for goal in goals {
+                match self.get_first(goal.clone()) {
+                    Some(main_target) => {
+                        for target in self.chain_commands(main_target.clone()) {
+                            if seen.insert(target.name.clone()) {
+                                order.push(target);
+                            }
+                        }
+                    }
+                    None => {
+                        RMakeError!("No rule to make target: {}", goal.unwrap());
+                    }
                 }
+            }
+            order
+        }
+
+        /// Log the reason a target is rebuilt, at INFO level in explain mode
+        ///
+        /// # Arguments:
+        ///
+        /// * target - The stale RMakeTarget
+        /// * reason - Why it is stale
+        fn log_reason(&self, target: &RMakeTarget, reason: &RMakeReason) {
+            if self.options.explain {
+                info!("Rebuilding {}: {}", target.name, reason);
             } else {
-                RMakeError!("No rule to make target: {}", name.unwrap());
+                debug!("Rebuilding {}: {}", target.name, reason);
+            }
+        }
+
+        /// Find which targets of the requested goals would be rebuilt, without running anything
+        ///
+        /// # Arguments:
+        ///
+        /// * names - The goal names, the first target is used if empty
+        ///
+        /// Returns the targets in build order, with the reason if they are stale
+        pub fn plan(&self, names: &[String]) -> Vec<(RMakeTarget, Option<RMakeReason>)> {
+            let state = RMakeState::load(&self.state_path());
+            let mut rebuilt = HashSet::new();
+            let mut plan = vec![];
+            for target in self.build_order(names) {
+                let reason = self.rebuild_reason(&target, &rebuilt, &state);
+                if reason.is_some() {
+                    rebuilt.insert(target.name.clone());
+                }
+                plan.push((target, reason));
+            }
+            plan
+        }
+
+        /// Check if the requested goals are up to date, without running anything
+        ///
+        /// # Arguments:
+        ///
+        /// * names - The goal names, the first target is used if empty
+        ///
+        /// Returns true if no target needs to be rebuilt
+        pub fn question(&self, names: Vec<String>) -> bool {
+            let state = RMakeState::load(&self.state_path());
+            for target in self.build_order(&names) {
+                /* A single stale target is enough, the ones depending on it are stale too */
+                if let Some(reason) = self.rebuild_reason(&target, &HashSet::new(), &state) {
+                    self.log_reason(&target, &reason);
+                    return false;
+                }
+            }
+            true
+        }
+
+        /// Run the RMake system
+        ///
+        /// # Arguments:
+        ///
+        /// * names - The goal names, the first target is used if empty
+        pub fn run(&mut self, names: Vec<String>) {
+            let mut state = RMakeState::load(&self.state_path());
+            let mut rebuilt = HashSet::new();
+            let mut recorded = false;
+            for target in self.build_order(&names) {
+                match self.rebuild_reason(&target, &rebuilt, &state) {
+                    Some(reason) => self.log_reason(&target, &reason),
+                    None => {
+                        debug!("Target {} is up to date", target.name);
+                        continue;
+                    }
+                }
+
+                if self.options.touch {
+                    /* Mark the outputs up to date instead of running the commands */
+                    if target.phony {
+                        continue;
+                    }
+                    for output in target.outputs.iter() {
+                        info!("Touching: {}", output);
+                        if let Err(e) = RMakeUtils::touch(&self.resolve(output)) {
+                            RMakeError!("Cannot touch {}: {}", output, e);
+                        }
+                    }
+                    rebuilt.insert(target.name.clone());
+
+                    let implicit_deps = match state.targets.get(&target.name) {
+                        Some(recorded) => recorded.implicit_deps.clone(),
+                        None => vec![],
+                    };
+                    let target_state = self.target_state(&target, &implicit_deps);
+                    state.record(&target.name, target_state, Duration::ZERO);
+                } else if self.options.dry_run {
+                    if let Some(cwd) = &target.cwd {
+                        info!("Would enter directory: {}", cwd);
+                    }
+                    /* Like make -n, the commands go to stdout whatever the log level */
+                    for cmd in target.cmds.iter().filter(|cmd| !cmd.trim().is_empty()) {
+                        println!("{}", cmd);
+                    }
+                    rebuilt.insert(target.name.clone());
+                    continue;
+                } else {
+                    let started = Instant::now();
+                    let env = self.command_env(&target);
+                    let dir = match &target.cwd {
+                        Some(cwd) => self.resolve(cwd),
+                        None => self.root.clone(),
+                    };
+                    if !dir.is_dir() {
+                        RMakeError!(
+                            "The cwd {} of target {} is not a directory",
+                            dir.display(),
+                            target.name
+                        );
+                    }
+                    if let Some(cwd) = &target.cwd {
+                        info!("Entering directory: {}", cwd);
+                    }
+                    for cmd in target.cmds.iter() {
+                        let split_cmd = cmd.split_whitespace().collect::<Vec<_>>();
+                        if split_cmd.is_empty() {
+                            continue;
+                        }
+
+                        info!("Running: {}", cmd);
+                        let status = std::process::Command::new(split_cmd[0])
+                            .args(&split_cmd[1..])
+                            .envs(&env)
+                            .current_dir(&dir)
+                            .status();
+                        let success = match status {
+                            Ok(status) => status.success(),
+                            Err(e) => {
+                                if recorded {
+                                    self.save_state(&state);
+                                }
+                                RMakeError!(
+                                    "Cannot run command for target {}: {}: {}",
+                                    target.name,
+                                    cmd,
+                                    e
+                                );
+                            }
+                        };
+                        if !success {
+                            /* Keep what the targets built so far recorded */
+                            if recorded {
+                                self.save_state(&state);
+                            }
+                            RMakeError!("Command failed for target {}: {}", target.name, cmd);
+                        }
+                    }
+                    rebuilt.insert(target.name.clone());
+
+                    let implicit_deps = match &target.depfile {
+                        Some(depfile) => RMakeUtils::read_depfile(&self.resolve(depfile), &target),
+                        None => vec![],
+                    };
+                    let target_state = self.target_state(&target, &implicit_deps);
+                    state.record(&target.name, target_state, started.elapsed());
+                }
+                recorded = true;
+            }
+
+            if recorded {
+                self.save_state(&state);
+            }
+            if rebuilt.is_empty() {
+                info!("Nothing to be done, targets are up to date");
             }
         }
     }
@@ -314,14 +1211,14 @@ pub mod rmake {
 
         /// Create RMakeTarget from a YAML Mapping object
         ///
+        /// The mapping is expected to be checked by validate::validate first.
+        ///
         /// # Arguments:
         ///
         /// * name - The name of the target
         /// * mapping - The Mapping object
         pub fn from_mapping(name: String, mapping: &Mapping) -> RMakeTarget {
-            if !mapping.contains_key("cmd") {
-                RMakeError!("A target must have cmd field!");
-            }
+            /* The field types are checked by validate, values of other types are ignored here */
 
             /* Construct dependencies names */
             let mut deps_strings: Vec<String> = vec![];
@@ -351,91 +1248,286 @@ pub mod rmake {
              */
             let mut cmds_list: Vec<String> = vec![];
 
-            let cmds = mapping.get("cmd").unwrap();
-            match cmds.as_str() {
-                Some(s_content) => {
+            match mapping.get("cmd") {
+                Some(Value::String(s_content)) => {
                     /* Split the conent by \n */
                     for s in s_content.split("\n") {
                         cmds_list.push(s.to_string());
                     }
                 }
-                None => {
-                    /* Try parsing as Sequence */
-                    match cmds.as_sequence() {
-                        Some(seq_content) => {
-                            for seq_elem in seq_content {
-                                match seq_elem.as_str() {
-                                    Some(cmd) => cmds_list.push(cmd.to_string()),
-                                    None => {
-                                        RMakeError!("Command in the Sequence is not String");
-                                    }
-                                }
-                            }
-                        }
-                        None => {
-                            /* Format is neither Sequence nor String */
-                            RMakeError!("Command list is not Sequence nor String !");
-                        }
+                Some(Value::Sequence(seq_content)) => {
+                    for cmd in seq_content.iter().filter_map(|v| v.as_str()) {
+                        cmds_list.push(cmd.to_string());
+                    }
+                }
+                _ => {}
+            }
+
+            /* Construct outputs, a target produces a file named after it by default */
+            let mut outputs: Vec<String> = vec![];
+            if let Some(outs) = mapping.get("outputs") {
+                if let Some(out) = outs.as_str() {
+                    outputs.push(out.to_string());
+                } else if let Some(seq) = outs.as_sequence() {
+                    for out in seq.iter().filter_map(|v| v.as_str()) {
+                        outputs.push(out.to_string());
                     }
                 }
             }
+            if outputs.is_empty() {
+                outputs.push(name.clone());
+            }
+
+            let depfile = match mapping.get("depfile") {
+                Some(Value::String(depfile)) => Some(depfile.clone()),
+                _ => None,
+            };
+
+            /* Target-specific variables and environment, converted like the global variables */
+            let vars = RMakeTarget::string_mapping(mapping, "vars");
+            let env = RMakeTarget::string_mapping(mapping, "env");
 
-            let ret_deps = if deps_strings.len() > 0 {
+            let cwd = match mapping.get("cwd") {
+                Some(Value::String(cwd)) => Some(cwd.clone()),
+                _ => None,
+            };
+
+            let inherit_vars = match mapping.get("inherit_vars") {
+                Some(Value::Bool(inherit_vars)) => *inherit_vars,
+                _ => false,
+            };
+
+            let phony = match mapping.get("phony") {
+                Some(Value::Bool(phony)) => *phony,
+                _ => false,
+            };
+
+            let desc = match mapping.get("desc") {
+                Some(Value::String(desc)) => Some(desc.clone()),
+                _ => None,
+            };
+
+            /* Targets prefixed with _ are hidden by default */
+            let hidden = match mapping.get("hidden") {
+                Some(Value::Bool(hidden)) => *hidden,
+                _ => name.starts_with('_'),
+            };
+
+            let ret_deps = if !deps_strings.is_empty() {
                 Some(deps_strings)
             } else {
                 None
             };
 
             RMakeTarget {
-                name: name,
+                name,
                 deps: ret_deps,
+                raw_cmds: cmds_list.clone(),
                 cmds: cmds_list,
+                outputs,
+                raw_depfile: depfile.clone(),
+                depfile,
+                phony,
+                location: None,
+                desc,
+                hidden,
+                vars,
+                inherit_vars,
+                raw_env: env.clone(),
+                env,
+                cwd,
+            }
+        }
+
+        /// Read a field of a target mapping NAME: value entries, like vars
+        ///
+        /// # Arguments:
+        ///
+        /// * mapping - The target Mapping
+        /// * key - The field name
+        ///
+        /// Returns the entries with values converted to Strings, empty if the field is missing
+        fn string_mapping(mapping: &Mapping, key: &str) -> HashMap<String, String> {
+            let mut entries = HashMap::new();
+            if let Some(Value::Mapping(field_map)) = mapping.get(key) {
+                for (entry_key, value) in field_map {
+                    let entry_name = match entry_key.as_str() {
+                        Some(entry_name) => entry_name.to_string(),
+                        None => continue,
+                    };
+                    if let Some(var) = RMakeVariable::from_value(entry_name.clone(), value) {
+                        entries.insert(entry_name, var.value);
+                    }
+                }
             }
+            entries
         }
 
-        /// Loop through all commands and expand them
+        /// Expand all variables and special characters of a given String
         ///
         /// # Arguments:
         ///
+        /// * value - The String to expand
         /// * variables - Optional list of all variables of the YAML file
-        fn expand_commands(&mut self, variables: &Option<RMakeVariables>) {
-            let mut final_commands = vec![];
-            for command in self.cmds.clone().into_iter() {
-                debug!("Expanding command variable: ({})", command);
+        /// * root - The build root, where shell commands run
+        ///
+        /// Returns the expanded String
+        fn expand(&self, value: String, variables: &Option<RMakeVariables>, root: &Path) -> String {
+            debug!("Expanding command variable: ({})", value);
+
+            let target = self.located(root);
+            let value = crate::RMakeExpandCommand!(target, value, variables, root, var);
+            let value = crate::RMakeExpandCommand!(target, value, variables, root, target_name);
+            let value = crate::RMakeExpandCommand!(target, value, variables, root, dep_all);
+            let value = crate::RMakeExpandCommand!(target, value, variables, root, dep_first);
+
+            debug!(" --------------- \n");
+            value
+        }
 
-                let cmd = crate::RMakeExpandCommand!(self, command, variables, var);
-                let cmd = crate::RMakeExpandCommand!(self, cmd, variables, target_name);
-                let cmd = crate::RMakeExpandCommand!(self, cmd, variables, dep_all);
-                let cmd = crate::RMakeExpandCommand!(self, cmd, variables, dep_first);
+        /// Expand only $@, $^ and $<, as seen from the directory the commands run in
+        ///
+        /// # Arguments:
+        ///
+        /// * value - The String to expand
+        /// * root - The build root
+        ///
+        /// Returns the expanded String
+        pub fn expand_automatic(&self, value: &str, root: &Path) -> String {
+            let target = self.located(root);
+            let variables: &Option<RMakeVariables> = &None;
+            let value = value.to_string();
+            let value = crate::RMakeExpandCommand!(target, value, variables, root, target_name);
+            let value = crate::RMakeExpandCommand!(target, value, variables, root, dep_all);
+            crate::RMakeExpandCommand!(target, value, variables, root, dep_first)
+        }
 
-                final_commands.push(cmd.clone());
-                debug!(" --------------- \n");
+        /// Copy of the target with its name, dependencies and outputs relative to its cwd,
+        /// so $@, $^ and $< are valid paths for its commands
+        ///
+        /// # Arguments:
+        ///
+        /// * root - The build root
+        pub fn located(&self, root: &Path) -> RMakeTarget {
+            let mut target = self.clone();
+            if let Some(cwd) = &self.cwd {
+                target.name = RMakeUtils::relative_to(&self.name, cwd, root);
+                target.deps = self.deps.as_ref().map(|deps| {
+                    deps.iter()
+                        .map(|dep| RMakeUtils::relative_to(dep, cwd, root))
+                        .collect()
+                });
+                target.outputs = self
+                    .outputs
+                    .iter()
+                    .map(|output| RMakeUtils::relative_to(output, cwd, root))
+                    .collect();
+            }
+            target
+        }
+
+        /// Merge the target-specific variables over the global ones
+        ///
+        /// Command line variables are kept, since they override everything.
+        ///
+        /// # Arguments:
+        ///
+        /// * variables - Optional list of all variables of the YAML file
+        /// * inherited - Variables inherited from the targets depending on this one
+        ///
+        /// Returns the variables to use for this target
+        pub fn scoped_variables(
+            &self,
+            variables: &Option<RMakeVariables>,
+            inherited: &HashMap<String, String>,
+        ) -> Option<RMakeVariables> {
+            if self.vars.is_empty() && inherited.is_empty() {
+                return variables.clone();
+            }
+
+            /* The own variables of the target win over the inherited ones */
+            let mut merged = variables.clone().unwrap_or_default();
+            for (name, value) in inherited.iter().chain(self.vars.iter()) {
+                let from_command_line = merged
+                    .get(name)
+                    .is_some_and(|var| var.origin == RMakeOrigin::CommandLine);
+                if !from_command_line {
+                    merged.insert(
+                        name.clone(),
+                        RMakeVariable {
+                            name: name.clone(),
+                            value: value.clone(),
+                            location: None,
+                            origin: RMakeOrigin::Target,
+                        },
+                    );
+                }
+            }
+            Some(merged)
+        }
+
+        /// Loop through all commands and expand them, along with the depfile path
+        ///
+        /// # Arguments:
+        ///
+        /// * variables - Optional list of all variables of the YAML file
+        /// * inherited - Variables inherited from the targets depending on this one
+        /// * root - The build root, where shell commands run
+        fn expand_commands(
+            &mut self,
+            variables: &Option<RMakeVariables>,
+            inherited: &HashMap<String, String>,
+            root: &Path,
+        ) {
+            let variables = self.scoped_variables(variables, inherited);
+
+            let mut final_commands = vec![];
+            for command in self.raw_cmds.clone().into_iter() {
+                final_commands.push(self.expand(command, &variables, root));
             }
             self.cmds = final_commands;
+
+            /* The depfile is written from the cwd, but read from the RMakefile directory */
+            if let Some(depfile) = self.raw_depfile.clone() {
+                let depfile = self.expand(depfile, &variables, root);
+                self.depfile = match &self.cwd {
+                    Some(cwd) => Some(RMakeUtils::in_dir(&depfile, cwd)),
+                    None => Some(depfile),
+                };
+            }
+
+            let mut env = HashMap::new();
+            for (name, value) in self.raw_env.clone().into_iter() {
+                env.insert(name, self.expand(value, &variables, root));
+            }
+            self.env = env;
         }
     }
 
     #[allow(non_snake_case)]
     mod RMakeUtils {
 
-        use super::{RMakeCoreCommand, RMakeVariables};
+        use super::{RMakeCoreCommand, RMakeTarget, RMakeVariables};
         use crate::RMakeError;
         //use paste::paste;
         use regex::Regex;
+        use std::path::{Component, Path};
         use std::process::Command;
         use std::str::FromStr;
+        use std::time::SystemTime;
         use tracing::{debug, error, warn};
 
         #[macro_export]
         macro_rules! RMakeExpandCommand {
-            ($self:ident, $command:ident, $variables:ident, $re:expr) => {
+            ($self:ident, $command:ident, $variables:ident, $root:ident, $re:expr) => {
                 paste::paste! {
                     RMakeUtils::find_and_replace(
                         $command.clone(),
                         RMakeUtils::[<rmake_re_ $re>](),
                         $variables,
                         &$self.name,
-                        &$self.deps
+                        &$self.deps,
+                        $root
                     )
                 }
             };
@@ -443,7 +1535,7 @@ pub mod rmake {
 
         const RMAKE_RE_VARIABLE: &str = r"\$\(([^)]+)\)";
         const RMAKE_RE_TARGET_NAME: &str = r"\$@";
-        const RMAKE_RE_DEP_ALL: &str = r"\$^";
+        const RMAKE_RE_DEP_ALL: &str = r"\$\^";
         const RMAKE_RE_DEP_FIRST: &str = r"\$<";
 
         pub fn rmake_re_var() -> Regex {
@@ -462,6 +1554,162 @@ pub mod rmake {
             Regex::new(RMAKE_RE_DEP_FIRST).unwrap()
         }
 
+        /// Get the modification time of a file
+        ///
+        /// # Arguments:
+        ///
+        /// * path - The file path
+        ///
+        /// Returns None if the file does not exist
+        pub fn mtime(path: &Path) -> Option<SystemTime> {
+            std::fs::metadata(path).and_then(|m| m.modified()).ok()
+        }
+
+        /// Set the modification time of a file to now, creating it if needed
+        ///
+        /// # Arguments:
+        ///
+        /// * path - The file path
+        pub fn touch(path: &Path) -> std::io::Result<()> {
+            std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?
+                .set_modified(SystemTime::now())
+        }
+
+        /// Split a path in its components, resolving . and .. without touching the filesystem
+        ///
+        /// # Arguments:
+        ///
+        /// * path - The relative path
+        fn components(path: &str) -> Vec<String> {
+            let mut parts: Vec<String> = vec![];
+            for part in Path::new(path).components() {
+                match part {
+                    Component::CurDir => {}
+                    Component::ParentDir if parts.last().is_some_and(|p| p != "..") => {
+                        parts.pop();
+                    }
+                    other => parts.push(other.as_os_str().to_string_lossy().to_string()),
+                }
+            }
+            parts
+        }
+
+        /// Join path components back, the empty path being the current directory
+        ///
+        /// # Arguments:
+        ///
+        /// * parts - The path components
+        fn join(parts: &[String]) -> String {
+            if parts.is_empty() {
+                String::from(".")
+            } else {
+                parts.join("/")
+            }
+        }
+
+        /// Rewrite a path relative to the RMakefile directory to be relative to a subdirectory
+        ///
+        /// # Arguments:
+        ///
+        /// * path - The path relative to the RMakefile directory
+        /// * dir - The subdirectory, relative to the RMakefile directory
+        /// * root - The RMakefile directory
+        ///
+        /// Returns the path seen from dir, absolute if dir is outside of the RMakefile directory
+        pub fn relative_to(path: &str, dir: &str, root: &Path) -> String {
+            if Path::new(path).is_absolute() {
+                return path.to_string();
+            }
+
+            let path_parts = components(path);
+            let dir_parts = components(dir);
+            if Path::new(dir).is_absolute() || dir_parts.iter().any(|p| p == "..") {
+                return root.join(join(&path_parts)).to_string_lossy().to_string();
+            }
+
+            let common = path_parts
+                .iter()
+                .zip(dir_parts.iter())
+                .take_while(|(a, b)| a == b)
+                .count();
+            let mut parts = vec![String::from(".."); dir_parts.len() - common];
+            parts.extend(path_parts[common..].iter().cloned());
+            join(&parts)
+        }
+
+        /// Rewrite a path relative to a subdirectory to be relative to the RMakefile directory
+        ///
+        /// # Arguments:
+        ///
+        /// * path - The path relative to dir
+        /// * dir - The subdirectory, relative to the RMakefile directory
+        pub fn in_dir(path: &str, dir: &str) -> String {
+            if Path::new(path).is_absolute() {
+                return path.to_string();
+            }
+            join(&components(&format!("{}/{}", dir, path)))
+        }
+
+        /// Read the prerequisites listed in a Makefile-syntax depfile, like the
+        /// ones written by `gcc -MMD`
+        ///
+        /// # Arguments:
+        ///
+        /// * path - The depfile path
+        /// * target - The RMakeTarget that wrote it
+        ///
+        /// Returns the prerequisites that are not already explicit dependencies
+        pub fn read_depfile(path: &Path, target: &RMakeTarget) -> Vec<String> {
+            let content = match std::fs::read_to_string(path) {
+                Ok(content) => content,
+                Err(e) => {
+                    warn!(
+                        "Cannot read depfile {} of {}: {}",
+                        path.display(),
+                        target.name,
+                        e
+                    );
+                    return vec![];
+                }
+            };
+
+            let mut implicit_deps: Vec<String> = vec![];
+            for rule in content.replace("\\\n", " ").lines() {
+                /* Everything after "<outputs>:" is a prerequisite */
+                let prerequisites = match rule.split_once(": ") {
+                    Some((_, prerequisites)) => prerequisites,
+                    None => match rule.strip_suffix(':') {
+                        Some(_) => continue,
+                        None => {
+                            debug!("Ignoring depfile line: {}", rule);
+                            continue;
+                        }
+                    },
+                };
+
+                for dep in prerequisites.split_whitespace() {
+                    /* Paths written from the cwd of the target */
+                    let dep = match &target.cwd {
+                        Some(cwd) => in_dir(dep, cwd),
+                        None => dep.to_string(),
+                    };
+                    let is_explicit = target.deps.iter().flatten().any(|d| *d == dep);
+                    if !is_explicit && !implicit_deps.contains(&dep) {
+                        implicit_deps.push(dep);
+                    }
+                }
+            }
+
+            debug!(
+                "Implicit dependencies of {}: {:?}",
+                target.name, implicit_deps
+            );
+            implicit_deps
+        }
+
         /// Find a regex and replace it in all the given String
         ///
         /// # Arguments:
@@ -469,6 +1717,7 @@ pub mod rmake {
         /// * value - The full String input
         /// * re - The Regex
         /// * variables - The full RMake variable list
+        /// * root - The build root, where shell commands run
         ///
         /// Returns the processed String input
         pub fn find_and_replace(
@@ -477,6 +1726,7 @@ pub mod rmake {
             variables: &Option<RMakeVariables>,
             target_name: &String,
             target_deps: &Option<Vec<String>>,
+            root: &Path,
         ) -> String {
             let mut value = value;
             debug!("[find_and_replace] Looking for {:?} in {} ..", re, value);
@@ -495,13 +1745,17 @@ pub mod rmake {
                         if target_deps.is_none() {
                             warn!("Using $^ without providing any deps ! Ignoring ..");
                         }
-                        to = target_deps.clone().unwrap().join(" ");
+                        to = target_deps.clone().unwrap_or_default().join(" ");
                     }
                     "$<" => {
                         if target_deps.is_none() {
                             warn!("Using $< without providing any deps ! Ignoring ..");
                         }
-                        to = target_deps.clone().unwrap().get(0).unwrap().clone();
+                        to = target_deps
+                            .as_ref()
+                            .and_then(|deps| deps.first())
+                            .cloned()
+                            .unwrap_or_default();
                     }
                     &_ => {
                         let found_str = &found_str[2..found_str.len() - 1];
@@ -528,6 +1782,7 @@ pub mod rmake {
                                         variables,
                                         target_name,
                                         target_deps,
+                                        root,
                                     );
                                     debug!("Expanded variable: {}", to);
                                     check_env = false;
@@ -554,18 +1809,26 @@ pub mod rmake {
                                     RMakeCoreCommand::Shell => {
                                         /* Run a Shell command and set (to) */
                                         let mut shell_command = Command::new(found_str_elems[1]);
-
-                                        for i in 2..found_str_elems.len() - 1 {
-                                            shell_command.arg(found_str_elems[i]);
+                                        shell_command.current_dir(root);
+
+                                        for elem in found_str_elems
+                                            .iter()
+                                            .take(found_str_elems.len() - 1)
+                                            .skip(2)
+                                        {
+                                            shell_command.arg(elem);
                                         }
 
-                                        to = String::from_utf8(
+                                        let output = String::from_utf8(
                                             shell_command
                                                 .output()
                                                 .expect("Cannot execute command!")
                                                 .stdout,
                                         )
                                         .unwrap();
+
+                                        /* Like make, newlines of the output become spaces */
+                                        to = output.trim_end_matches('\n').replace('\n', " ");
                                     }
                                     RMakeCoreCommand::Wildcard => {
                                         warn!("wildcard is not yet supported!")
@@ -586,5 +1849,117 @@ pub mod rmake {
 
             value
         }
+
+        #[cfg(test)]
+        mod tests {
+            use super::*;
+
+            #[test]
+            fn components_resolve_dots() {
+                assert_eq!(components("a/./b/../c"), vec!["a", "c"]);
+                assert_eq!(components("./a/"), vec!["a"]);
+                assert_eq!(components("../a"), vec!["..", "a"]);
+                assert_eq!(components("a/../../b"), vec!["..", "b"]);
+                assert!(components("a/..").is_empty());
+            }
+
+            #[test]
+            fn relative_to_subdirectories() {
+                let root = Path::new("/proj");
+                assert_eq!(relative_to("lib/f.c", "lib", root), "f.c");
+                assert_eq!(relative_to("main.c", "lib", root), "../main.c");
+                assert_eq!(relative_to("lib", "lib", root), ".");
+                assert_eq!(relative_to("lib/a/b.c", "lib/b/", root), "../a/b.c");
+                assert_eq!(relative_to("./lib/../src/x.c", "lib", root), "../src/x.c");
+            }
+
+            #[test]
+            fn relative_to_shared_prefixes() {
+                let root = Path::new("/proj");
+                assert_eq!(relative_to("libfoo/x.c", "lib", root), "../libfoo/x.c");
+                assert_eq!(relative_to("lib/x.c", "libfoo", root), "../lib/x.c");
+            }
+
+            #[test]
+            fn relative_to_outside_of_the_root() {
+                let root = Path::new("/proj");
+                assert_eq!(
+                    relative_to("/usr/include/x.h", "lib", root),
+                    "/usr/include/x.h"
+                );
+                assert_eq!(relative_to("../x.c", "lib", root), "../../x.c");
+                assert_eq!(relative_to("a.c", "../out", root), "/proj/a.c");
+                assert_eq!(relative_to("a/../b.c", "/tmp", root), "/proj/b.c");
+                assert_eq!(relative_to("a.c", "lib/../..", root), "/proj/a.c");
+            }
+
+            #[test]
+            fn in_dir_joins_the_subdirectory() {
+                assert_eq!(in_dir("f.d", "lib"), "lib/f.d");
+                assert_eq!(in_dir("../f.d", "lib"), "f.d");
+                assert_eq!(in_dir("../../f.d", "lib"), "../f.d");
+                assert_eq!(in_dir(".", "lib/"), "lib");
+                assert_eq!(in_dir("/abs/f.d", "lib"), "/abs/f.d");
+            }
+
+            /// Write a depfile in the temporary directory and read it for a target
+            fn depfile_deps(name: &str, yaml: &str, content: &str) -> Vec<String> {
+                let mapping = serde_yaml::from_str(yaml).unwrap();
+                let target = RMakeTarget::from_mapping(name.to_string(), &mapping);
+                let path = std::env::temp_dir().join(format!(
+                    "rmake-test-{}-{}.d",
+                    std::process::id(),
+                    name
+                ));
+                std::fs::write(&path, content).unwrap();
+                let deps = read_depfile(&path, &target);
+                std::fs::remove_file(&path).unwrap();
+                deps
+            }
+
+            #[test]
+            fn read_depfile_skips_phony_rules() {
+                /* gcc -MP adds an empty rule for every header */
+                let deps = depfile_deps(
+                    "phony.o",
+                    "cmd: gcc -MMD -MP -c phony.c",
+                    "phony.o: phony.c hello.h\n\nhello.h:\n",
+                );
+                assert_eq!(deps, vec!["phony.c", "hello.h"]);
+            }
+
+            #[test]
+            fn read_depfile_joins_continuations() {
+                let deps = depfile_deps(
+                    "cont.o",
+                    "cmd: gcc -MMD -c cont.c",
+                    "cont.o: cont.c \\\n  include/a.h \\\n  include/b.h\n",
+                );
+                assert_eq!(deps, vec!["cont.c", "include/a.h", "include/b.h"]);
+            }
+
+            #[test]
+            fn read_depfile_resolves_the_cwd() {
+                let deps = depfile_deps(
+                    "cwd.o",
+                    "cmd: gcc -MMD -c cwd.c\ncwd: lib",
+                    "cwd.o: cwd.c ../include/a.h /usr/include/stdio.h\n",
+                );
+                assert_eq!(
+                    deps,
+                    vec!["lib/cwd.c", "include/a.h", "/usr/include/stdio.h"]
+                );
+            }
+
+            #[test]
+            fn read_depfile_filters_explicit_deps() {
+                let deps = depfile_deps(
+                    "explicit.o",
+                    "dep: [explicit.c]\ncmd: gcc -MMD -c $<",
+                    "explicit.o: explicit.c hello.h hello.h\n",
+                );
+                assert_eq!(deps, vec!["hello.h"]);
+            }
+        }
     }
 }
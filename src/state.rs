@@ -0,0 +1,236 @@
+use crate::rmake::rmake::RMakeTarget;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{debug, warn};
+
+/// The state file name, stored in the build directory
+pub const RMAKE_STATE_FILE: &str = ".rmake_state";
+
+/// The version of the state file format, bump it on incompatible changes
+pub const RMAKE_STATE_VERSION: u32 = 1;
+
+/// This represents what is recorded for a target after a successful build
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RMakeTargetState {
+    /// SHA-256 digest of every input file, by file name, only recorded in hash mode
+    pub inputs: BTreeMap<String, String>,
+
+    /// SHA-256 digest of the expanded command lines, their environment and directory
+    pub command: String,
+
+    /// Dependencies discovered from the depfile of the target, like included headers
+    #[serde(default)]
+    pub implicit_deps: Vec<String>,
+
+    /// Time of the last successful build, in seconds since the UNIX epoch
+    #[serde(default)]
+    pub built_at: u64,
+
+    /// Duration of the last successful build, in milliseconds
+    #[serde(default)]
+    pub duration_ms: u64,
+}
+
+impl RMakeTargetState {
+    /// Create the state of a target from the digest of its commands, without input digests
+    ///
+    /// # Arguments:
+    ///
+    /// * command - The digest of its commands, from RMake::command_digest
+    /// * implicit_deps - The dependencies discovered from its depfile
+    pub fn new(command: String, implicit_deps: &[String]) -> RMakeTargetState {
+        RMakeTargetState {
+            inputs: BTreeMap::new(),
+            command,
+            implicit_deps: implicit_deps.to_vec(),
+            built_at: 0,
+            duration_ms: 0,
+        }
+    }
+
+    /// Compute the digests of the inputs of a target, for hash mode
+    ///
+    /// Inputs that do not exist as files are skipped.
+    ///
+    /// # Arguments:
+    ///
+    /// * target - The RMakeTarget
+    /// * root - The build root the inputs are relative to
+    pub fn hash_inputs(&mut self, target: &RMakeTarget, root: &Path) {
+        for dep in target.deps.iter().flatten().chain(&self.implicit_deps) {
+            if let Ok(content) = std::fs::read(root.join(dep)) {
+                self.inputs.insert(dep.clone(), digest(&content));
+            }
+        }
+    }
+}
+
+/// This represents the state of all targets built in a directory
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RMakeState {
+    /// The format version of the state file, files written before versioning are 0
+    #[serde(default)]
+    pub version: u32,
+
+    /// The recorded state of each target, by target name
+    pub targets: BTreeMap<String, RMakeTargetState>,
+}
+
+impl Default for RMakeState {
+    fn default() -> Self {
+        RMakeState {
+            version: RMAKE_STATE_VERSION,
+            targets: BTreeMap::new(),
+        }
+    }
+}
+
+impl RMakeState {
+    /// Load the state file, a missing, broken or newer file gives an empty state
+    ///
+    /// # Arguments:
+    ///
+    /// * path - The state file path
+    pub fn load(path: &Path) -> RMakeState {
+        let reader = match std::fs::File::open(path) {
+            Ok(reader) => reader,
+            Err(_) => {
+                debug!("No state file found at {}", path.display());
+                return RMakeState::default();
+            }
+        };
+
+        match serde_yaml::from_reader::<_, RMakeState>(reader) {
+            Ok(state) => state.upgrade(path),
+            Err(e) => {
+                warn!("Ignoring invalid state file {}: {}", path.display(), e);
+                RMakeState::default()
+            }
+        }
+    }
+
+    /// Bring a loaded state to the current format version
+    ///
+    /// # Arguments:
+    ///
+    /// * path - The state file path, used for logging
+    fn upgrade(mut self, path: &Path) -> RMakeState {
+        if self.version > RMAKE_STATE_VERSION {
+            warn!(
+                "Ignoring state file {} written by a newer rmake (version {})",
+                path.display(),
+                self.version
+            );
+            return RMakeState::default();
+        }
+
+        /* Version 0 only lacks build times and durations, which default to 0 */
+        if self.version < RMAKE_STATE_VERSION {
+            debug!(
+                "Upgrading state file {} from version {}",
+                path.display(),
+                self.version
+            );
+            self.version = RMAKE_STATE_VERSION;
+        }
+        self
+    }
+
+    /// Record a successful build of a target
+    ///
+    /// # Arguments:
+    ///
+    /// * name - The name of the target that was built
+    /// * target_state - Its current state, from RMake::target_state
+    /// * duration - How long running its commands took
+    pub fn record(&mut self, name: &str, mut target_state: RMakeTargetState, duration: Duration) {
+        target_state.built_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        target_state.duration_ms = duration.as_millis() as u64;
+        self.targets.insert(name.to_string(), target_state);
+    }
+
+    /// Write the state file
+    ///
+    /// The content is written to a temporary file first then renamed, so an
+    /// interrupted build never leaves a truncated state file behind.
+    ///
+    /// # Arguments:
+    ///
+    /// * path - The state file path
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let content = serde_yaml::to_string(self)?;
+        let tmp_path = path.with_extension("tmp");
+        std::fs::write(&tmp_path, content)?;
+        std::fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+}
+
+/// Compute the SHA-256 digest of some bytes
+///
+/// # Arguments:
+///
+/// * bytes - The content to hash
+///
+/// Returns the digest as a lowercase hex String
+pub fn digest(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Write a state file in the temporary directory and load it
+    fn load_state(name: &str, content: &str) -> RMakeState {
+        let path = std::env::temp_dir().join(format!("rmake-test-{}-{}", std::process::id(), name));
+        std::fs::write(&path, content).unwrap();
+        let state = RMakeState::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        state
+    }
+
+    #[test]
+    fn load_upgrades_version_0() {
+        /* Written before versioning, without implicit deps and build times */
+        let state = load_state(
+            "v0",
+            "targets:\n  main:\n    inputs:\n      main.c: abc\n    command: def\n",
+        );
+        assert_eq!(state.version, RMAKE_STATE_VERSION);
+        let main = &state.targets["main"];
+        assert_eq!(main.inputs["main.c"], "abc");
+        assert_eq!(main.command, "def");
+        assert!(main.implicit_deps.is_empty());
+        assert_eq!(main.built_at, 0);
+    }
+
+    #[test]
+    fn load_ignores_newer_versions() {
+        let state = load_state(
+            "newer",
+            &format!(
+                "version: {}\ntargets:\n  main:\n    inputs: {{}}\n    command: def\n",
+                RMAKE_STATE_VERSION + 1
+            ),
+        );
+        assert_eq!(state.version, RMAKE_STATE_VERSION);
+        assert!(state.targets.is_empty());
+    }
+
+    #[test]
+    fn load_ignores_corrupt_files() {
+        let state = load_state("corrupt", "targets: [main\n");
+        assert_eq!(state.version, RMAKE_STATE_VERSION);
+        assert!(state.targets.is_empty());
+    }
+}
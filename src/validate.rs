@@ -0,0 +1,593 @@
+use crate::rmake::rmake::{RMakeLocation, RMAKE_EXPORT_KEY};
+use serde_yaml::{Mapping, Value};
+use std::collections::HashMap;
+use yaml_rust2::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust2::scanner::{Marker, TScalarStyle};
+
+/// The keys a target can have
+const RMAKE_TARGET_KEYS: [&str; 11] = [
+    "cmd",
+    "dep",
+    "outputs",
+    "depfile",
+    "phony",
+    "desc",
+    "hidden",
+    "vars",
+    "inherit_vars",
+    "env",
+    "cwd",
+];
+
+/// This represents an error or a warning found in the RMakefile
+#[derive(Debug)]
+pub struct RMakeDiagnostic {
+    /// Where the problem is, if known
+    pub location: Option<RMakeLocation>,
+
+    /// The description of the problem
+    pub message: String,
+
+    /// Errors stop the loading, warnings are only reported
+    pub is_error: bool,
+}
+
+impl std::fmt::Display for RMakeDiagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = if self.is_error { "error" } else { "warning" };
+        match &self.location {
+            Some(location) => write!(f, "{}: {}: {}", location, kind, self.message),
+            None => write!(f, "{}: {}", kind, self.message),
+        }
+    }
+}
+
+/// A key of an RMakefile, the top-level name and the key inside the target, if any
+///
+/// Target names can contain dots, so "main.o" and the key o of main stay apart.
+pub type RMakeKey = (String, Option<String>);
+
+/// Where the keys of an RMakefile are defined, and the numbers of its variables
+#[derive(Debug, Default)]
+pub struct RMakeSources {
+    /// The locations of the top-level keys and of the keys of the targets
+    pub locations: HashMap<RMakeKey, RMakeLocation>,
+
+    /// The written and read numbers of the variables that do not read back as written,
+    /// by path: [NAME] for a top-level variable, [target, "vars" or "env", NAME] for the ones of a target
+    pub numbers: HashMap<Vec<String>, Vec<(String, String)>>,
+}
+
+/// A collection being parsed, mappings remember the key waiting for its value
+enum RMakeFrame {
+    Mapping(Option<String>),
+    Sequence,
+}
+
+/// This collects the sources of an RMakefile from the YAML parser events
+struct RMakeLocator<'a> {
+    /// The RMakefile path
+    file: &'a str,
+
+    /// The collections being parsed, outermost first
+    stack: Vec<RMakeFrame>,
+
+    sources: RMakeSources,
+}
+
+impl RMakeLocator<'_> {
+    /// Record a key or a value scalar
+    ///
+    /// # Arguments:
+    ///
+    /// * value - The scalar value
+    /// * plain - If the scalar has no quotes nor tag, so YAML may read it as a number
+    /// * mark - The position of the scalar
+    fn scalar(&mut self, value: String, plain: bool, mark: Marker) {
+        let is_key = matches!(self.stack.last(), Some(RMakeFrame::Mapping(None)));
+        let path = self
+            .stack
+            .iter()
+            .filter_map(|frame| match frame {
+                RMakeFrame::Mapping(Some(key)) => Some(key.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        if is_key && path.len() <= 1 {
+            let key = match path.first() {
+                Some(parent) => (parent.to_string(), Some(value.clone())),
+                None => (value.clone(), None),
+            };
+            self.sources.locations.entry(key).or_insert(RMakeLocation {
+                file: self.file.to_string(),
+                line: mark.line(),
+                column: mark.col() + 1,
+            });
+        }
+
+        /* Variables are converted to Strings, so 1.10 becomes 1.1 and 0x1F becomes 31 */
+        let is_variable = match path.as_slice() {
+            [_] => true,
+            [_, field, _] => *field == "vars" || *field == "env",
+            _ => false,
+        };
+        if !is_key && plain && is_variable {
+            if let Ok(Value::Number(number)) = serde_yaml::from_str::<Value>(&value) {
+                let read = number.to_string();
+                if read != value {
+                    self.sources
+                        .numbers
+                        .entry(path.iter().map(|key| key.to_string()).collect())
+                        .or_default()
+                        .push((value.clone(), read));
+                }
+            }
+        }
+
+        if let Some(RMakeFrame::Mapping(key)) = self.stack.last_mut() {
+            *key = if is_key { Some(value) } else { None };
+        }
+    }
+}
+
+impl MarkedEventReceiver for RMakeLocator<'_> {
+    fn on_event(&mut self, event: Event, mark: Marker) {
+        match event {
+            Event::Scalar(value, style, _, tag) => {
+                self.scalar(value, style == TScalarStyle::Plain && tag.is_none(), mark)
+            }
+            /* The anchored value is reported where it is defined */
+            Event::Alias(_) => self.scalar(String::new(), false, mark),
+            Event::MappingStart(..) => self.stack.push(RMakeFrame::Mapping(None)),
+            Event::SequenceStart(..) => self.stack.push(RMakeFrame::Sequence),
+            Event::MappingEnd | Event::SequenceEnd => {
+                self.stack.pop();
+                if let Some(RMakeFrame::Mapping(key)) = self.stack.last_mut() {
+                    *key = None;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Find where each key is defined in the raw YAML content, and the
+/// numbers of the variables that do not read back as written
+///
+/// The parser stops at the first syntax error, serde_yaml reports it.
+///
+/// # Arguments:
+///
+/// * path - The RMakefile path
+/// * content - The raw content of the RMakefile
+///
+/// Returns the sources of the RMakefile
+pub fn locate(path: &str, content: &str) -> RMakeSources {
+    let mut locator = RMakeLocator {
+        file: path,
+        stack: vec![],
+        sources: RMakeSources::default(),
+    };
+    let _ = Parser::new_from_str(content).load(&mut locator, false);
+    locator.sources
+}
+
+/// Name of a key for messages, "target.key" for the keys of a target
+///
+/// # Arguments:
+///
+/// * name - The top-level key name
+/// * key - The key inside the target, if any
+fn key_path(name: &str, key: Option<&str>) -> String {
+    match key {
+        Some(key) => format!("{}.{}", name, key),
+        None => name.to_string(),
+    }
+}
+
+/// Name of the type of a YAML Value, for messages
+///
+/// # Arguments:
+///
+/// * value - The YAML Value
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Sequence(_) => "list",
+        Value::Mapping(_) => "mapping",
+        Value::Tagged(_) => "tagged value",
+    }
+}
+
+/// Number of single character edits between two Strings
+///
+/// # Arguments:
+///
+/// * a - The first String
+/// * b - The second String
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous + if ca == *cb { 0 } else { 1 };
+            previous = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// This collects the diagnostics of an RMakefile
+struct RMakeValidator<'a> {
+    sources: &'a RMakeSources,
+    diagnostics: Vec<RMakeDiagnostic>,
+}
+
+impl RMakeValidator<'_> {
+    /// Report a problem at the location of a key
+    ///
+    /// # Arguments:
+    ///
+    /// * name - The top-level key name
+    /// * key - The key inside the target, if any
+    /// * message - The description of the problem
+    /// * is_error - If the problem stops the loading
+    fn report(&mut self, name: &str, key: Option<&str>, message: String, is_error: bool) {
+        /* Fall back to the location of the target, like for keys coming from an alias */
+        let locations = &self.sources.locations;
+        let location = locations
+            .get(&(name.to_string(), key.map(String::from)))
+            .or_else(|| locations.get(&(name.to_string(), None)));
+
+        self.diagnostics.push(RMakeDiagnostic {
+            location: location.cloned(),
+            message,
+            is_error,
+        });
+    }
+
+    /// Check that a target field is a String or a list of Strings
+    ///
+    /// # Arguments:
+    ///
+    /// * name - The top-level key name
+    /// * key - The key inside the target, if any
+    /// * value - The YAML Value of the field
+    fn expect_strings(&mut self, name: &str, key: Option<&str>, value: &Value) {
+        let path = key_path(name, key);
+        match value {
+            Value::String(_) => {}
+            Value::Sequence(seq) => {
+                for (index, item) in seq.iter().enumerate() {
+                    if !item.is_string() {
+                        self.report(
+                            name,
+                            key,
+                            format!(
+                                "{} item {} must be a string, found {}",
+                                path,
+                                index + 1,
+                                type_name(item)
+                            ),
+                            true,
+                        );
+                    }
+                }
+            }
+            _ => self.report(
+                name,
+                key,
+                format!(
+                    "{} must be a string or a list of strings, found {}",
+                    path,
+                    type_name(value)
+                ),
+                true,
+            ),
+        }
+    }
+
+    /// Report the numbers of a variable that do not read back as written
+    ///
+    /// # Arguments:
+    ///
+    /// * name - The top-level key name
+    /// * key - The key inside the target, if any
+    /// * variable - The path of the variable, as in RMakeSources::numbers
+    fn numbers(&mut self, name: &str, key: Option<&str>, variable: &[&str]) {
+        let path = variable
+            .iter()
+            .map(|part| part.to_string())
+            .collect::<Vec<_>>();
+        let numbers = self.sources.numbers.get(&path).cloned().unwrap_or_default();
+        for (written, read) in numbers {
+            let message = format!(
+                "{} is written {} but reads as {}, quote it to keep it as written",
+                variable.join("."),
+                written,
+                read
+            );
+            self.report(name, key, message, false);
+        }
+    }
+
+    /// Check that a variable converts to a String without losing anything
+    ///
+    /// # Arguments:
+    ///
+    /// * name - The top-level key name
+    /// * key - The key inside the target, for the variables of a target
+    /// * path - The name of the checked value, for nested sequence items
+    /// * value - The YAML Value of the variable
+    fn variable(&mut self, name: &str, key: Option<&str>, path: &str, value: &Value) {
+        let seq = match value {
+            Value::Sequence(seq) => seq,
+            _ => return,
+        };
+
+        for (index, item) in seq.iter().enumerate() {
+            let item_path = format!("{} item {}", path, index + 1);
+            match item {
+                Value::Sequence(_) => {
+                    self.report(
+                        name,
+                        key,
+                        format!("{} is a list, it is flattened", item_path),
+                        false,
+                    );
+                    self.variable(name, key, &item_path, item);
+                }
+                Value::Mapping(_) | Value::Tagged(_) | Value::Null => self.report(
+                    name,
+                    key,
+                    format!("{} is a {}, it is dropped", item_path, type_name(item)),
+                    false,
+                ),
+                _ => {}
+            }
+        }
+    }
+
+    /// Check the fields of a target
+    ///
+    /// # Arguments:
+    ///
+    /// * name - The target name
+    /// * mapping - The target Mapping
+    fn target(&mut self, name: &str, mapping: &Mapping) {
+        if !mapping.contains_key("cmd") {
+            self.report(
+                name,
+                None,
+                format!("target {} must have a cmd field", name),
+                true,
+            );
+        }
+
+        for (key, value) in mapping {
+            let key = match key.as_str() {
+                Some(key) => key,
+                None => {
+                    self.report(
+                        name,
+                        None,
+                        format!(
+                            "target {} has a key that is not a string: {}",
+                            name,
+                            type_name(key)
+                        ),
+                        true,
+                    );
+                    continue;
+                }
+            };
+            let path = key_path(name, Some(key));
+
+            match key {
+                "cmd" | "dep" | "outputs" => self.expect_strings(name, Some(key), value),
+                "depfile" | "desc" | "cwd" => {
+                    if !value.is_string() {
+                        let message =
+                            format!("{} must be a string, found {}", path, type_name(value));
+                        self.report(name, Some(key), message, true);
+                    }
+                }
+                "vars" | "env" => match value {
+                    Value::Mapping(vars) => {
+                        for (var_key, var_value) in vars {
+                            match var_key.as_str() {
+                                Some(var_name) => match var_value {
+                                    Value::Mapping(_) | Value::Tagged(_) => {
+                                        let message = format!(
+                                            "{}.{} must be a string, found {}",
+                                            path,
+                                            var_name,
+                                            type_name(var_value)
+                                        );
+                                        self.report(name, Some(key), message, true);
+                                    }
+                                    _ => {
+                                        self.numbers(name, Some(key), &[name, key, var_name]);
+                                        self.variable(
+                                            name,
+                                            Some(key),
+                                            &format!("{}.{}", path, var_name),
+                                            var_value,
+                                        );
+                                    }
+                                },
+                                None => {
+                                    let message =
+                                        format!("{} has a key that is not a string", path);
+                                    self.report(name, Some(key), message, true);
+                                }
+                            }
+                        }
+                    }
+                    _ => {
+                        let message =
+                            format!("{} must be a mapping, found {}", path, type_name(value));
+                        self.report(name, Some(key), message, true);
+                    }
+                },
+                "phony" | "hidden" | "inherit_vars" => {
+                    if !value.is_bool() {
+                        let message =
+                            format!("{} must be a boolean, found {}", path, type_name(value));
+                        self.report(name, Some(key), message, true);
+                    }
+                }
+                _ => {
+                    let suggestion = RMAKE_TARGET_KEYS
+                        .iter()
+                        .find(|known| edit_distance(known, key) <= 2)
+                        .map(|known| format!(", did you mean {}?", known))
+                        .unwrap_or_default();
+                    let message = format!("unknown key {} is ignored{}", path, suggestion);
+                    self.report(name, Some(key), message, false);
+                }
+            }
+        }
+    }
+}
+
+/// Check the structure of an RMakefile
+///
+/// # Arguments:
+///
+/// * global_map - The global mapping for the YAML file
+/// * sources - The locations of the keys and the numbers of the variables, from locate
+///
+/// Returns all errors and warnings found
+pub fn validate(global_map: &Mapping, sources: &RMakeSources) -> Vec<RMakeDiagnostic> {
+    let mut validator = RMakeValidator {
+        sources,
+        diagnostics: vec![],
+    };
+
+    for (key, value) in global_map {
+        let name = match key.as_str() {
+            Some(name) => name,
+            None => {
+                validator.diagnostics.push(RMakeDiagnostic {
+                    location: None,
+                    message: format!("top-level key {:?} must be a string", key),
+                    is_error: true,
+                });
+                continue;
+            }
+        };
+
+        if name == RMAKE_EXPORT_KEY {
+            validator.expect_strings(name, None, value);
+            continue;
+        }
+
+        match value {
+            Value::Mapping(mapping) => validator.target(name, mapping),
+            Value::Tagged(_) => validator.report(
+                name,
+                None,
+                format!("variable {} is a tagged value, it is ignored", name),
+                false,
+            ),
+            _ => {
+                validator.numbers(name, None, &[name]);
+                validator.variable(name, None, name, value);
+            }
+        }
+    }
+
+    validator.diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(
+        locations: &HashMap<RMakeKey, RMakeLocation>,
+        name: &str,
+        key: Option<&str>,
+    ) -> (usize, usize) {
+        let location = &locations[&(name.to_string(), key.map(String::from))];
+        (location.line, location.column)
+    }
+
+    #[test]
+    fn locate_keys_block_and_flow_mappings() {
+        let content = "CC: gcc\nmain:\n  cmd: echo\n  dep: [a, b]\nflow: {dep: x, cmd: echo}\n";
+        let locations = locate("RMakefile.yml", content).locations;
+        assert_eq!(position(&locations, "CC", None), (1, 1));
+        assert_eq!(position(&locations, "main", Some("cmd")), (3, 3));
+        assert_eq!(position(&locations, "main", Some("dep")), (4, 3));
+        assert_eq!(position(&locations, "flow", None), (5, 1));
+        assert_eq!(position(&locations, "flow", Some("cmd")), (5, 16));
+        assert!(!locations.contains_key(&("a".to_string(), None)));
+    }
+
+    #[test]
+    fn locate_keys_quoted_and_nested_keys() {
+        let content = "\"main.o\":\n  cmd: cc\n  vars:\n    CFLAGS: -O2\n? \"long\n  key\"\n: 1\n";
+        let locations = locate("RMakefile.yml", content).locations;
+        assert_eq!(position(&locations, "main.o", Some("vars")), (3, 3));
+        assert_eq!(position(&locations, "long key", None), (5, 3));
+        assert_eq!(locations.len(), 4);
+    }
+
+    #[test]
+    fn locate_keys_keeps_dotted_targets_apart() {
+        let content = "main:\n  o: x\nmain.o:\n  cmd: cc\n";
+        let locations = locate("RMakefile.yml", content).locations;
+        assert_eq!(position(&locations, "main", Some("o")), (2, 3));
+        assert_eq!(position(&locations, "main.o", None), (3, 1));
+    }
+
+    #[test]
+    fn locate_numbers_keeps_lossy_variables() {
+        let content =
+            "A: 1.10\nB: 12\nC: '1.10'\nD: [0x1F, 2]\nmain:\n  cmd: echo 1.0\n  vars: {V: 1e3}\n";
+        let numbers = locate("RMakefile.yml", content).numbers;
+        let pair = |written: &str, read: &str| vec![(written.to_string(), read.to_string())];
+        let path = |parts: &[&str]| {
+            parts
+                .iter()
+                .map(|part| part.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(numbers[&path(&["A"])], pair("1.10", "1.1"));
+        assert_eq!(numbers[&path(&["D"])], pair("0x1F", "31"));
+        assert_eq!(
+            numbers[&path(&["main", "vars", "V"])],
+            pair("1e3", "1000.0")
+        );
+        assert_eq!(numbers.len(), 3);
+    }
+
+    #[test]
+    fn report_falls_back_to_the_target_location() {
+        let content = "main.o: {cmd: cc, phony: 1}\n";
+        let sources = locate("RMakefile.yml", content);
+        let global_map: Mapping = serde_yaml::from_str(content).unwrap();
+        let mut validator = RMakeValidator {
+            sources: &sources,
+            diagnostics: vec![],
+        };
+        validator.report("main.o", Some("unknown"), String::new(), false);
+        validator.report("main.o", Some("phony"), String::new(), true);
+        let lines = validator
+            .diagnostics
+            .iter()
+            .map(|d| d.location.as_ref().map(|l| (l.line, l.column)))
+            .collect::<Vec<_>>();
+        assert_eq!(lines, vec![Some((1, 1)), Some((1, 19))]);
+        assert_eq!(validate(&global_map, &sources).len(), 1);
+    }
+}